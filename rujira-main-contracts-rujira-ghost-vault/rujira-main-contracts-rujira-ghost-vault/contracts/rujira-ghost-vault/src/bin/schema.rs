@@ -1,12 +1,12 @@
 use cosmwasm_schema::write_api;
 
-use rujira_rs::ghost::vault;
+use rujira_ghost_vault::msg;
 
 fn main() {
     write_api! {
-        instantiate: vault::InstantiateMsg,
-        execute: vault::ExecuteMsg,
-        query: vault::QueryMsg,
-        sudo: vault::SudoMsg,
+        instantiate: msg::InstantiateMsg,
+        execute: msg::ExecuteMsg,
+        query: msg::QueryMsg,
+        sudo: msg::SudoMsg,
     }
 }
@@ -1,81 +1,382 @@
-use crate::ContractError;
+use crate::{
+    config::Config,
+    interest::{compound, YEAR},
+    state::State,
+    ContractError,
+};
 use cosmwasm_schema::cw_serde;
-use cosmwasm_std::{Addr, Order, StdError, StdResult, Storage, Uint128};
+use cosmwasm_std::{
+    Addr, Decimal, Decimal256, Order, StdError, StdResult, Storage, Timestamp, Uint128,
+};
 use cw_storage_plus::{Bound, Map};
 use rujira_rs::SharePool;
 use std::{
-    cmp::min,
+    cmp::{max, min},
     collections::HashMap,
     ops::{Add, Sub},
 };
 
 static BORROWERS: Map<Addr, Borrower> = Map::new("borrowers");
-// Delegated shares for a borrower
-static DELEGATE_SHARES: Map<(Addr, Addr), Uint128> = Map::new("delegates");
+// Each delegate's units of a borrower's debt
+static DELEGATE_UNITS: Map<(Addr, Addr), Uint128> = Map::new("delegates");
+// Caps on a single delegate's debt, set by the borrower
+static DELEGATE_LIMITS: Map<(Addr, Addr), Uint128> = Map::new("delegate_limits");
+
+#[cw_serde]
+#[derive(Default)]
+pub enum BorrowerStatus {
+    #[default]
+    Active,
+    /// Being offboarded. New borrows are blocked, and the debt is charged `premium` on top of
+    /// the pool's debt rate until it is repaid
+    WindDown { premium: Decimal },
+}
+
+/// A borrow limit that scales with the vault's total deposits
+#[cw_serde]
+pub struct DepositLimit {
+    /// The fraction of total deposits that can be borrowed
+    pub fraction: Decimal,
+    /// Bounds on the scaled limit
+    pub floor: Option<Uint128>,
+    pub ceiling: Option<Uint128>,
+}
+
+impl DepositLimit {
+    pub fn validate(&self) -> Result<(), ContractError> {
+        if self.fraction > Decimal::one() {
+            return Err(ContractError::Invalid("limit.fraction".to_string()));
+        }
+        if let (Some(floor), Some(ceiling)) = (self.floor, self.ceiling) {
+            if floor > ceiling {
+                return Err(ContractError::Invalid("limit.floor".to_string()));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn limit(&self, deposits: Uint128) -> Uint128 {
+        let limit = deposits.mul_floor(self.fraction);
+        let limit = self.floor.map_or(limit, |floor| max(limit, floor));
+        self.ceiling.map_or(limit, |ceiling| min(limit, ceiling))
+    }
+}
 
 #[cw_serde]
 pub struct Borrower {
     pub addr: Addr,
     pub limit: Uint128,
+    /// The debt pool shares owed by the borrower
     pub shares: Uint128,
+    /// Claims on `shares`, split between the borrower's delegates and its own undelegated
+    /// debt. Premiums add shares without adding units, so every claim grows in proportion
+    #[serde(default)]
+    pub units: Uint128,
+    /// Replaces the absolute `limit` when set
+    #[serde(default)]
+    pub deposit_limit: Option<DepositLimit>,
+    #[serde(default)]
+    pub status: BorrowerStatus,
+    /// Charged on the borrower's debt on top of the pool's debt rate, priced to its risk
+    #[serde(default)]
+    pub spread: Decimal,
+    /// When the borrower's premium was last charged
+    #[serde(default)]
+    pub premium_updated: Option<Timestamp>,
+    /// Premium debt shares accrued short of a whole share, carried to the next charge
+    #[serde(default)]
+    pub pending_premium: Decimal256,
 }
 
 impl Borrower {
     pub fn load(storage: &dyn Storage, addr: Addr) -> Result<Self, ContractError> {
         match BORROWERS.load(storage, addr.clone()) {
-            Ok(x) => Ok(x),
+            Ok(x) => Ok(x.normalize()),
             Err(StdError::NotFound { .. }) => Err(ContractError::UnauthorizedBorrower {}),
             Err(err) => Err(ContractError::Std(err)),
         }
     }
 
+    /// Borrowers saved before debt was split into units hold one unit per share
+    fn normalize(mut self) -> Self {
+        if self.units.is_zero() {
+            self.units = self.shares;
+        }
+        self
+    }
+
     pub fn save(&self, storage: &mut dyn Storage) -> StdResult<()> {
         BORROWERS.save(storage, self.addr.clone(), self)
     }
 
-    pub fn delegate_shares(&self, storage: &dyn Storage, delegate: Addr) -> Uint128 {
-        DELEGATE_SHARES
+    /// The debt shares owed on `units`, rounded up
+    fn value(&self, units: Uint128) -> Uint128 {
+        if self.units.is_zero() {
+            return units;
+        }
+        min(units.mul_ceil((self.shares, self.units)), self.shares)
+    }
+
+    /// The units that `shares` of the borrower's debt are worth, rounded down
+    fn to_units(&self, shares: Uint128) -> Uint128 {
+        if self.shares.is_zero() {
+            return shares;
+        }
+        shares.mul_floor((self.units, self.shares))
+    }
+
+    fn delegate_units(&self, storage: &dyn Storage, delegate: Addr) -> Uint128 {
+        DELEGATE_UNITS
             .load(storage, (self.addr.clone(), delegate))
             .unwrap_or_default()
     }
 
+    fn delegated_units(&self, storage: &dyn Storage) -> StdResult<Uint128> {
+        DELEGATE_UNITS
+            .prefix(self.addr.clone())
+            .range(storage, None, None, Order::Ascending)
+            .try_fold(Uint128::zero(), |total, x| Ok(total.add(x?.1)))
+    }
+
+    pub fn delegate_shares(&self, storage: &dyn Storage, delegate: Addr) -> Uint128 {
+        self.value(self.delegate_units(storage, delegate))
+    }
+
+    pub fn delegate_limit(&self, storage: &dyn Storage, delegate: Addr) -> Option<Uint128> {
+        DELEGATE_LIMITS
+            .may_load(storage, (self.addr.clone(), delegate))
+            .unwrap_or_default()
+    }
+
+    /// Caps the debt of one of the borrower's delegates. `None` removes the cap
+    pub fn set_delegate_limit(
+        &self,
+        storage: &mut dyn Storage,
+        delegate: Addr,
+        limit: Option<Uint128>,
+    ) -> StdResult<()> {
+        let k = (self.addr.clone(), delegate);
+        match limit {
+            Some(limit) => DELEGATE_LIMITS.save(storage, k, &limit),
+            None => {
+                DELEGATE_LIMITS.remove(storage, k);
+                Ok(())
+            }
+        }
+    }
+
     pub fn delegate_borrow(
         &mut self,
         storage: &mut dyn Storage,
         delegate: Addr,
+        state: &State,
+        shares: Uint128,
+    ) -> Result<(), ContractError> {
+        let units = self.add(state, shares)?;
+        self.delegate_add(storage, delegate, &state.debt_pool, units)?;
+        Ok(self.save(storage)?)
+    }
+
+    /// Allocates units to a delegate, within any cap the borrower has set on it
+    fn delegate_add(
+        &self,
+        storage: &mut dyn Storage,
+        delegate: Addr,
         pool: &SharePool,
+        units: Uint128,
+    ) -> Result<(), ContractError> {
+        let current = self.delegate_units(storage, delegate.clone());
+        if let Some(limit) = self
+            .delegate_limit(storage, delegate.clone())
+            .filter(|limit| pool.ownership(self.value(current.add(units))).gt(limit))
+        {
+            return Err(ContractError::DelegateLimitReached {
+                delegate: delegate.to_string(),
+                limit,
+            });
+        }
+        Ok(DELEGATE_UNITS.save(storage, (self.addr.clone(), delegate), &current.add(units))?)
+    }
+
+    /// Moves debt between the borrower's own obligations, `None` being the debt borrowed
+    /// without a delegate
+    pub fn transfer(
+        &self,
+        storage: &mut dyn Storage,
+        pool: &SharePool,
+        from: Option<Addr>,
+        to: Option<Addr>,
         shares: Uint128,
     ) -> Result<(), ContractError> {
-        DELEGATE_SHARES.update(
-            storage,
-            (self.addr.clone(), delegate),
-            |v| -> Result<Uint128, ContractError> { Ok(v.unwrap_or_default().add(shares)) },
-        )?;
-        self.borrow(storage, pool, shares)
+        let available = match &from {
+            Some(delegate) => self.delegate_units(storage, delegate.clone()),
+            None => self.units.checked_sub(self.delegated_units(storage)?)?,
+        };
+        let owed = self.value(available);
+        if shares.gt(&owed) {
+            return Err(ContractError::InsufficientDebt {
+                shares: owed,
+                requested: shares,
+            });
+        }
+        let units = if shares == owed {
+            available
+        } else {
+            self.to_units(shares)
+        };
+        if let Some(delegate) = from {
+            DELEGATE_UNITS.save(
+                storage,
+                (self.addr.clone(), delegate),
+                &available.sub(units),
+            )?;
+        }
+        if let Some(delegate) = to {
+            self.delegate_add(storage, delegate, pool, units)?;
+        }
+        Ok(())
+    }
+
+    /// Moves all of the borrower's debt, and the delegates it is allocated to, onto another
+    /// borrower within that borrower's limit. The caps set on its delegates move with them,
+    /// the lower applying where both borrowers cap a delegate, and each delegate's debt must
+    /// fit within its cap on the other borrower. Returns the shares moved
+    pub fn transfer_all(
+        &mut self,
+        storage: &mut dyn Storage,
+        state: &State,
+        to: &mut Borrower,
+    ) -> Result<Uint128, ContractError> {
+        let shares = self.shares;
+        let units = to.add(state, shares)?;
+        let limits = DELEGATE_LIMITS
+            .prefix(self.addr.clone())
+            .range(storage, None, None, Order::Ascending)
+            .collect::<StdResult<Vec<(Addr, Uint128)>>>()?;
+        for (delegate, limit) in limits {
+            DELEGATE_LIMITS.remove(storage, (self.addr.clone(), delegate.clone()));
+            DELEGATE_LIMITS.update(
+                storage,
+                (to.addr.clone(), delegate),
+                |v| -> StdResult<Uint128> { Ok(v.map_or(limit, |v| min(v, limit))) },
+            )?;
+        }
+        let delegates = DELEGATE_UNITS
+            .prefix(self.addr.clone())
+            .range(storage, None, None, Order::Ascending)
+            .collect::<StdResult<Vec<(Addr, Uint128)>>>()?;
+        // Delegates keep their proportion of the debt. Rounding the running total means a
+        // fully delegated book stays fully delegated
+        let (mut moved, mut issued) = (Uint128::zero(), Uint128::zero());
+        for (delegate, delegate_units) in delegates {
+            DELEGATE_UNITS.remove(storage, (self.addr.clone(), delegate.clone()));
+            moved += delegate_units;
+            let total = if self.units.is_zero() {
+                moved
+            } else {
+                moved.mul_floor((units, self.units))
+            };
+            let scaled = total.sub(issued);
+            issued = total;
+            to.delegate_add(storage, delegate, &state.debt_pool, scaled)?;
+        }
+        to.save(storage)?;
+        self.shares = Uint128::zero();
+        self.units = Uint128::zero();
+        self.save(storage)?;
+        Ok(shares)
     }
 
     pub fn borrow(
         &mut self,
         storage: &mut dyn Storage,
-        pool: &SharePool,
+        state: &State,
         shares: Uint128,
     ) -> Result<(), ContractError> {
-        if pool.ownership(self.shares.add(shares)).gt(&self.limit) {
-            return Err(ContractError::BorrowLimitReached { limit: self.limit });
-        }
-        self.shares += shares;
+        self.add(state, shares)?;
         Ok(self.save(storage)?)
     }
 
+    /// Adds new debt within the borrower's limit, returning the units issued for it
+    fn add(&mut self, state: &State, shares: Uint128) -> Result<Uint128, ContractError> {
+        self.check_limit(state, shares)?;
+        let units = if self.shares.is_zero() {
+            shares
+        } else {
+            shares.checked_mul_ceil((self.units, self.shares))?
+        };
+        self.units += units;
+        self.shares += shares;
+        Ok(units)
+    }
+
+    /// The borrower's limit in absolute terms, given the vault's current deposits
+    pub fn effective_limit(&self, state: &State) -> Uint128 {
+        match &self.deposit_limit {
+            Some(limit) => limit.limit(state.deposit_pool.size()),
+            None => self.limit,
+        }
+    }
+
+    /// Checks that borrowing a further `shares` keeps the borrower within its limit
+    pub fn check_limit(&self, state: &State, shares: Uint128) -> Result<(), ContractError> {
+        if let BorrowerStatus::WindDown { .. } = self.status {
+            return Err(ContractError::WindDown {});
+        }
+        let limit = self.effective_limit(state);
+        if state
+            .debt_pool
+            .ownership(self.shares.add(shares))
+            .gt(&limit)
+        {
+            return Err(ContractError::BorrowLimitReached { limit });
+        }
+        Ok(())
+    }
+
+    /// Clears up to `shares` of the debt owed on `available` units, returning the units and
+    /// shares cleared. Clearing all of them takes the debt rounded up
+    fn settle(&mut self, available: Uint128, shares: Uint128) -> (Uint128, Uint128) {
+        let owed = self.value(available);
+        let (units, shares) = if shares.ge(&owed) {
+            (available, owed)
+        } else {
+            (min(self.to_units(shares), available), shares)
+        };
+        self.units -= units;
+        self.shares -= shares;
+        (units, shares)
+    }
+
+    /// Saves the borrower, clearing its delegates once it holds no debt
+    fn save_settled(&mut self, storage: &mut dyn Storage) -> StdResult<()> {
+        if self.shares.is_zero() {
+            self.units = Uint128::zero();
+            let delegates = DELEGATE_UNITS
+                .prefix(self.addr.clone())
+                .keys(storage, None, None, Order::Ascending)
+                .collect::<StdResult<Vec<Addr>>>()?;
+            for delegate in delegates {
+                DELEGATE_UNITS.remove(storage, (self.addr.clone(), delegate));
+            }
+        }
+        self.save(storage)
+    }
+
+    /// Repays the borrower's debt, returning the shares in excess of it. The undelegated debt is
+    /// repaid first, any remainder reduces every delegate's debt in proportion
     pub fn repay(
         &mut self,
         storage: &mut dyn Storage,
         shares: Uint128,
     ) -> Result<Uint128, ContractError> {
-        let repaid = min(shares, self.shares);
-        self.shares -= repaid;
-        self.save(storage)?;
-        Ok(shares.sub(repaid))
+        let available = self.units.checked_sub(self.delegated_units(storage)?)?;
+        let (_, repaid) = self.settle(available, shares);
+        // Removing shares without units lowers the value of every delegate's units alike
+        let rest = min(shares.sub(repaid), self.shares);
+        self.shares -= rest;
+        self.save_settled(storage)?;
+        Ok(shares.sub(repaid).sub(rest))
     }
 
     pub fn delegate_repay(
@@ -85,23 +386,175 @@ impl Borrower {
         shares: Uint128,
     ) -> Result<Uint128, ContractError> {
         let k = (self.addr.clone(), delegate);
-        let delegate = DELEGATE_SHARES.load(storage, k.clone())?;
-        let repaid = min(shares, delegate);
-        DELEGATE_SHARES.save(storage, k, &delegate.checked_sub(repaid)?)?;
-        self.repay(storage, repaid)?;
+        let available = DELEGATE_UNITS.load(storage, k.clone())?;
+        let (units, repaid) = self.settle(available, shares);
+        DELEGATE_UNITS.save(storage, k, &available.sub(units))?;
+        self.save_settled(storage)?;
         Ok(shares.sub(repaid))
     }
 
+    /// Removes bad debt from the borrower, returning the shares written off. Either a single
+    /// delegate's debt or the borrower's undelegated debt is written off in part. Writing off
+    /// without a delegate or an amount takes all of the borrower's debt. Delegate obligations
+    /// are cleared once the borrower holds no debt
+    pub fn write_off(
+        &mut self,
+        storage: &mut dyn Storage,
+        delegate: Option<Addr>,
+        shares: Option<Uint128>,
+    ) -> Result<Uint128, ContractError> {
+        let written_off = match (delegate, shares) {
+            (Some(delegate), shares) => {
+                let k = (self.addr.clone(), delegate);
+                let available = DELEGATE_UNITS.load(storage, k.clone())?;
+                let owed = self.value(available);
+                let (units, written_off) = self.settle(available, shares.unwrap_or(owed));
+                DELEGATE_UNITS.save(storage, k, &available.sub(units))?;
+                written_off
+            }
+            (None, Some(shares)) => {
+                let available = self.units.checked_sub(self.delegated_units(storage)?)?;
+                self.settle(available, shares).1
+            }
+            (None, None) => {
+                let written_off = self.shares;
+                self.shares = Uint128::zero();
+                written_off
+            }
+        };
+        self.save_settled(storage)?;
+        Ok(written_off)
+    }
+
     pub fn set(storage: &mut dyn Storage, addr: Addr, limit: Uint128) -> StdResult<()> {
         let mut borrower = BORROWERS.load(storage, addr.clone()).unwrap_or(Borrower {
             addr: addr.clone(),
             limit: Default::default(),
             shares: Default::default(),
+            units: Default::default(),
+            deposit_limit: None,
+            status: Default::default(),
+            spread: Decimal::zero(),
+            premium_updated: None,
+            pending_premium: Decimal256::zero(),
         });
         borrower.limit = limit;
         BORROWERS.save(storage, addr, &borrower)
     }
 
+    /// Removes a borrower that holds no debt, along with its delegate caps
+    pub fn remove(&self, storage: &mut dyn Storage) -> Result<(), ContractError> {
+        if !self.shares.is_zero() {
+            return Err(ContractError::BorrowerHasDebt {
+                shares: self.shares,
+            });
+        }
+        for map in [&DELEGATE_UNITS, &DELEGATE_LIMITS] {
+            let delegates = map
+                .prefix(self.addr.clone())
+                .keys(storage, None, None, Order::Ascending)
+                .collect::<StdResult<Vec<Addr>>>()?;
+            for delegate in delegates {
+                map.remove(storage, (self.addr.clone(), delegate));
+            }
+        }
+        BORROWERS.remove(storage, self.addr.clone());
+        Ok(())
+    }
+
+    /// The rate charged on the borrower's debt on top of the pool's debt rate: its spread, plus
+    /// any wind-down premium
+    pub fn premium(&self) -> Decimal {
+        match self.status {
+            BorrowerStatus::Active => self.spread,
+            BorrowerStatus::WindDown { premium } => self.spread + premium,
+        }
+    }
+
+    /// The debt shares charged per year for the borrower's premium
+    pub fn premium_weight(&self) -> Decimal256 {
+        Decimal256::from_ratio(self.shares, 1u128) * Decimal256::from(self.premium())
+    }
+
+    /// Adds the borrower's premium on its current debt to the vault's premium rate
+    pub fn weigh(&self, state: &mut State) {
+        state.premium_rate += self.premium_weight();
+    }
+
+    /// Allocates the premium accrued since the borrower was last charged to its debt. The debt
+    /// is added without adding units, spreading it across the borrower's delegates in
+    /// proportion to their debt. The borrower's premium leaves the vault's premium rate, to be
+    /// restored by `weigh` once its debt has changed. Returns the amount charged and the fee
+    /// shares to mint
+    pub fn accrue_premium(
+        &mut self,
+        state: &mut State,
+        config: &Config,
+        now: Timestamp,
+    ) -> Result<(Uint128, Uint128), ContractError> {
+        let seconds = self
+            .premium_updated
+            .map_or(0, |since| now.seconds().saturating_sub(since.seconds()));
+        self.premium_updated = Some(now);
+        state.premium_rate = state
+            .premium_rate
+            .checked_sub(self.premium_weight())
+            .unwrap_or_default();
+        let shares = Decimal256::from_ratio(self.shares, 1u128);
+        let part = Decimal256::from(self.premium()) * Decimal256::from_ratio(seconds, YEAR);
+        // The vault has accrued simple premium on the borrower's debt since it was last charged.
+        // Compounding charges e^(rt) - 1, the difference being credited to depositors now
+        let simple = shares * part;
+        state.premium_accrued = state
+            .premium_accrued
+            .checked_sub(simple)
+            .unwrap_or_default();
+        let owed = self.pending_premium
+            + if config.compounding {
+                shares.checked_mul(compound(part)?)?
+            } else {
+                simple
+            };
+        let whole = owed.to_uint_floor();
+        self.pending_premium = owed - Decimal256::from_ratio(whole, 1u128);
+        let whole = Uint128::try_from(whole)?;
+        if whole.is_zero() {
+            return Ok((Uint128::zero(), Uint128::zero()));
+        }
+        let (shares, fees) = state.take_premium(whole, config)?;
+        // Whatever could not be issued yet is owed next time
+        self.pending_premium += Decimal256::from_ratio(whole.sub(shares), 1u128);
+        self.shares += shares;
+        Ok((state.debt_pool.ownership(shares), fees))
+    }
+
+    /// The borrower's delegates and the debt shares they owe, in address order
+    pub fn delegates<'a>(
+        &self,
+        storage: &'a dyn Storage,
+        limit: Option<u8>,
+        start_after: Option<Addr>,
+    ) -> impl Iterator<Item = StdResult<(Addr, Uint128)>> + 'a {
+        let limit = limit.unwrap_or(100) as usize;
+        let min = start_after.map(Bound::exclusive);
+        let borrower = self.clone();
+        DELEGATE_UNITS
+            .prefix(self.addr.clone())
+            .range(storage, min, None, Order::Ascending)
+            .take(limit)
+            .map(move |x| x.map(|(addr, units)| (addr, borrower.value(units))))
+    }
+
+    /// The debt shares owed across all of the borrower's delegates
+    pub fn delegated_shares(&self, storage: &dyn Storage) -> StdResult<Uint128> {
+        Ok(self.value(self.delegated_units(storage)?))
+    }
+
+    /// The debt shares the borrower owes without a delegate
+    pub fn undelegated_shares(&self, storage: &dyn Storage) -> StdResult<Uint128> {
+        Ok(self.shares.checked_sub(self.delegated_shares(storage)?)?)
+    }
+
     pub fn list(
         storage: &dyn Storage,
         limit: Option<u8>,
@@ -112,7 +565,7 @@ impl Borrower {
         BORROWERS
             .range(storage, min, None, Order::Ascending)
             .take(limit)
-            .map(|x| x.map(|(_, v)| v))
+            .map(|x| x.map(|(_, v)| v.normalize()))
     }
 }
 
@@ -135,7 +588,7 @@ pub fn migrate(storage: &mut dyn Storage) -> StdResult<()> {
     let mut delegate_shares_by_borrower: HashMap<Addr, Uint128> = HashMap::new();
 
     for ((borrower_addr, delegate_addr), old_delegate) in old_delegates {
-        DELEGATE_SHARES.save(
+        DELEGATE_UNITS.save(
             storage,
             (borrower_addr.clone(), delegate_addr),
             &old_delegate.shares,
@@ -150,8 +603,19 @@ pub fn migrate(storage: &mut dyn Storage) -> StdResult<()> {
     for (borrower_addr, expected_shares) in delegate_shares_by_borrower {
         let mut borrower = BORROWERS.load(storage, borrower_addr.clone())?;
         borrower.shares = expected_shares;
+        borrower.units = expected_shares;
         borrower.save(storage)?;
     }
 
     Ok(())
 }
+
+/// Splits each borrower's debt into units, one per share. Delegate debt was stored as shares
+/// under the same namespace the units now use, so it carries over as is
+pub fn migrate_units(storage: &mut dyn Storage) -> StdResult<()> {
+    let borrowers = BORROWERS
+        .range(storage, None, None, Order::Ascending)
+        .map(|x| x.map(|(_, v)| v.normalize()))
+        .collect::<StdResult<Vec<Borrower>>>()?;
+    borrowers.iter().try_for_each(|x| x.save(storage))
+}
@@ -1,18 +1,46 @@
 use cosmwasm_schema::cw_serde;
-use cosmwasm_std::{Addr, Api, Decimal, StdResult, Storage};
+use cosmwasm_std::{Addr, Api, Decimal, StdResult, Storage, Timestamp, Uint128};
 use cw_storage_plus::Item;
-use rujira_rs::ghost::vault::{InstantiateMsg, Interest};
 
-use crate::ContractError;
+use crate::{
+    interest::{InterestModel, Ramp},
+    msg::{InstantiateMsg, Pause},
+    ContractError,
+};
 
 static CONFIG: Item<Config> = Item::new("config");
 
 #[cw_serde]
 pub struct Config {
     pub denom: String,
-    pub interest: Interest,
+    pub interest: InterestModel,
     pub fee: Decimal,
     pub fee_address: Addr,
+    #[serde(default)]
+    pub guardian: Option<Addr>,
+    #[serde(default)]
+    pub pause: Pause,
+    #[serde(default)]
+    pub deposit_cap: Option<Uint128>,
+    #[serde(default)]
+    pub depositor_cap: Option<Uint128>,
+    /// Borrows are rejected beyond this utilization, keeping a buffer for withdrawals
+    #[serde(default = "Decimal::one")]
+    pub max_utilization: Decimal,
+    /// The share of protocol fees retained by the vault as a reserve against bad debt
+    #[serde(default)]
+    pub reserve_fraction: Decimal,
+    /// Set while rates are moving from a previous interest model to `interest`
+    #[serde(default)]
+    pub ramp: Option<Ramp>,
+    /// Accrue interest continuously compounded rather than simple over each period
+    #[serde(default)]
+    pub compounding: bool,
+    /// The fee charged on flash loans, as a fraction of the amount borrowed
+    #[serde(default)]
+    pub flash_fee: Decimal,
+    #[serde(default)]
+    pub flash_limit: Option<Uint128>,
 }
 
 impl Config {
@@ -22,6 +50,16 @@ impl Config {
             interest: value.interest,
             fee: value.fee,
             fee_address: api.addr_validate(value.fee_address.as_str())?,
+            guardian: None,
+            pause: Pause::default(),
+            deposit_cap: None,
+            depositor_cap: None,
+            max_utilization: Decimal::one(),
+            reserve_fraction: Decimal::zero(),
+            ramp: None,
+            compounding: false,
+            flash_fee: Decimal::zero(),
+            flash_limit: None,
         })
     }
 }
@@ -31,12 +69,69 @@ impl Config {
         CONFIG.load(storage)
     }
 
+    /// Clears a ramp that has reached its end, returning whether there was one. Rates are
+    /// then those of `interest` alone
+    pub fn end_ramp(&mut self, time: Timestamp) -> bool {
+        if self.ramp.as_ref().is_some_and(|x| x.end <= time) {
+            self.ramp = None;
+            return true;
+        }
+        false
+    }
+
     pub fn validate(&self) -> Result<(), ContractError> {
         if self.fee >= Decimal::one() {
             return Err(ContractError::Invalid("config.fee".to_string()));
         }
 
-        Ok(self.interest.validate()?)
+        if self.max_utilization.is_zero() || self.max_utilization > Decimal::one() {
+            return Err(ContractError::Invalid("config.max_utilization".to_string()));
+        }
+
+        if self.flash_fee >= Decimal::one() {
+            return Err(ContractError::Invalid("config.flash_fee".to_string()));
+        }
+
+        if self.reserve_fraction > Decimal::one() {
+            return Err(ContractError::Invalid(
+                "config.reserve_fraction".to_string(),
+            ));
+        }
+
+        self.interest.validate()
+    }
+
+    /// Checks the vault's total deposits and the depositor's position, both including
+    /// the new deposit, against the configured caps
+    pub fn check_deposit_cap(
+        &self,
+        total: Uint128,
+        depositor: Uint128,
+    ) -> Result<(), ContractError> {
+        if let Some(cap) = self.deposit_cap.filter(|cap| total.gt(cap)) {
+            return Err(ContractError::DepositCapExceeded {
+                cap,
+                deposits: total,
+            });
+        }
+        if let Some(cap) = self.depositor_cap.filter(|cap| depositor.gt(cap)) {
+            return Err(ContractError::DepositCapExceeded {
+                cap,
+                deposits: depositor,
+            });
+        }
+        Ok(())
+    }
+
+    /// Checks a flash loan against the configured limit, returning the fee owed on it
+    pub fn flash_fee(&self, amount: Uint128) -> Result<Uint128, ContractError> {
+        if let Some(limit) = self.flash_limit.filter(|limit| amount.gt(limit)) {
+            return Err(ContractError::FlashLoanLimit {
+                limit,
+                requested: amount,
+            });
+        }
+        Ok(amount.mul_ceil(self.flash_fee))
     }
 
     pub fn save(&self, storage: &mut dyn Storage) -> StdResult<()> {
@@ -47,6 +142,7 @@ impl Config {
 #[cfg(test)]
 mod tests {
     use cosmwasm_std::Decimal;
+    use rujira_rs::ghost::vault::Interest;
 
     use super::*;
 
@@ -54,16 +150,57 @@ mod tests {
     fn validation() {
         Config {
             denom: "btc".to_string(),
-            interest: Interest {
+            interest: InterestModel::Kink(Interest {
                 target_utilization: Decimal::from_ratio(8u128, 10u128),
                 base_rate: Decimal::from_ratio(3u128, 10000u128),
                 step1: Decimal::from_ratio(8u128, 10u128),
                 step2: Decimal::from_ratio(3u128, 1u128),
-            },
+            }),
             fee: Decimal::zero(),
             fee_address: Addr::unchecked("addr0000000000000000000000000000000000000000"),
+            guardian: None,
+            pause: Pause::default(),
+            deposit_cap: None,
+            depositor_cap: None,
+            max_utilization: Decimal::percent(95),
+            reserve_fraction: Decimal::percent(50),
+            ramp: None,
+            compounding: false,
+            flash_fee: Decimal::permille(9),
+            flash_limit: None,
         }
         .validate()
         .unwrap();
     }
+
+    #[test]
+    fn legacy_interest() {
+        // Configs saved before interest models were introduced hold the kink parameters directly
+        let mut storage = cosmwasm_std::testing::MockStorage::new();
+        let interest =
+            r#"{"target_utilization":"0.8","base_rate":"0.0003","step1":"0.8","step2":"3"}"#;
+        storage.set(
+            b"config",
+            format!(
+                r#"{{"denom":"btc","interest":{interest},"fee":"0.1","fee_address":"addr0000000000000000000000000000000000000000"}}"#
+            )
+            .as_bytes(),
+        );
+        let config = Config::load(&storage).unwrap();
+        assert_eq!(
+            config.interest,
+            InterestModel::Kink(Interest {
+                target_utilization: Decimal::from_ratio(8u128, 10u128),
+                base_rate: Decimal::from_ratio(3u128, 10000u128),
+                step1: Decimal::from_ratio(8u128, 10u128),
+                step2: Decimal::from_ratio(3u128, 1u128),
+            })
+        );
+        assert_eq!(config.fee, Decimal::percent(10));
+        // And is returned in the same shape
+        assert_eq!(
+            cosmwasm_std::to_json_string(&config.interest).unwrap(),
+            interest
+        );
+    }
 }
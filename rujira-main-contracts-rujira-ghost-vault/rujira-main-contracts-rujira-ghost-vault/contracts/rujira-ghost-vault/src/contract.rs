@@ -1,22 +1,35 @@
-use crate::borrowers::Borrower;
+use crate::borrowers::{Borrower, BorrowerStatus};
 use crate::config::Config;
 use crate::error::ContractError;
-use crate::events::{event_borrow, event_deposit, event_repay, event_withdraw};
-use crate::state::State;
+use crate::events::{
+    event_accrue, event_borrow, event_borrower_spread, event_borrower_status, event_config,
+    event_delegate_limit, event_deposit, event_flash_loan, event_fund_reserve, event_migrate_debt,
+    event_premium, event_remove_borrower, event_repay, event_sweep_reserve, event_transfer_debt,
+    event_withdraw, event_withdraw_cancel, event_withdraw_claim, event_withdraw_fill,
+    event_withdraw_queue, event_write_off,
+};
+use crate::flash::{FlashLoan, FLASH_LOAN_REPLY};
+use crate::interest::{InterestModel, Ramp};
+use crate::msg::{
+    BorrowerResponse, BorrowerTermsResponse, BorrowersResponse, ConfigResponse,
+    DelegateDebtResponse, DelegateResponse, DelegatesResponse, ExecuteMsg, GuardianMsg,
+    InstantiateMsg, InterestResponse, MarketMsg, Pause, PoolResponse, PositionResponse,
+    PositionsResponse, PreviewResponse, QueryMsg, RampResponse, ReserveResponse, SettingsResponse,
+    StatusResponse, SudoMsg, TicketResponse, WithdrawalQueueResponse,
+};
+use crate::queue::{fill, Ticket};
+use crate::state::{Accrual, State};
 #[cfg(not(feature = "library"))]
 use cosmwasm_std::entry_point;
 use cosmwasm_std::{
-    coins, to_json_binary, BankMsg, Binary, Deps, DepsMut, Empty, Env, MessageInfo, Response,
-    StdResult, Uint128,
+    coins, to_json_binary, to_json_string, Addr, BankMsg, Binary, Decimal, Deps, DepsMut, Empty,
+    Env, Event, MessageInfo, Reply, Response, StdResult, Storage, SubMsg, Timestamp, Uint128,
 };
-use cw2::set_contract_version;
+use cw2::{get_contract_version, set_contract_version};
 use cw_utils::must_pay;
-use rujira_rs::ghost::vault::{
-    BorrowerResponse, BorrowersResponse, ConfigResponse, DelegateResponse, ExecuteMsg,
-    InstantiateMsg, MarketMsg, PoolResponse, QueryMsg, StatusResponse, SudoMsg,
-};
-use rujira_rs::TokenFactory;
+use rujira_rs::{CallbackData, TokenFactory};
 use std::cmp::min;
+use std::ops::{Add, Sub};
 
 const CONTRACT_NAME: &str = env!("CARGO_PKG_NAME");
 const CONTRACT_VERSION: &str = env!("CARGO_PKG_VERSION");
@@ -40,19 +53,39 @@ pub fn instantiate( //*initializing the contract
 
 #[cfg_attr(not(feature = "library"), entry_point)]
 pub fn execute(
-    deps: DepsMut,
+    mut deps: DepsMut,
     env: Env,
     info: MessageInfo,
     msg: ExecuteMsg,
 ) -> Result<Response, ContractError> {
-    let config = Config::load(deps.storage)?;
+    FlashLoan::ensure_inactive(deps.storage)?;
+    let mut config = Config::load(deps.storage)?;
     let mut state = State::load(deps.storage)?;
     let rcpt = TokenFactory::new(&env, format!("ghost-vault/{}", config.denom).as_str());
-    let fees = state.distribute_interest(&env, &config)?;
+    let accrual = state.distribute_interest(&env, &config)?;
+    if config.end_ramp(env.block.time) {
+        config.save(deps.storage)?;
+    }
+    let accrued = event_accrual(&accrual, &state);
     let mut response = match msg {
-        ExecuteMsg::Deposit { callback } => {
+        ExecuteMsg::Deposit {
+            callback,
+            min_shares,
+            deadline,
+        } => {
+            ensure_active(config.pause.deposit, "deposit")?;
+            ensure_deadline(&env, deadline)?;
             let amount = must_pay(&info, config.denom.as_str())?;
+            let held = deps
+                .querier
+                .query_balance(info.sender.as_str(), rcpt.denom())?
+                .amount;
+            config.check_deposit_cap(
+                state.deposit_pool.size().add(amount),
+                state.deposit_pool.ownership(held).add(amount),
+            )?;
             let mint = state.deposit(amount)?;
+            ensure_minimum(min_shares, mint)?;
             state.save(deps.storage)?;
 
             match callback {
@@ -69,20 +102,38 @@ pub fn execute(
                     .add_event(event_deposit(info.sender, amount, mint)),
             }
         }
-        ExecuteMsg::Withdraw { callback } => {
-            let amount = must_pay(&info, rcpt.denom().as_str())?;
-            let withdrawn = state.withdraw(amount)?;
+        ExecuteMsg::Withdraw {
+            callback,
+            amount: exact,
+            min_amount,
+            deadline,
+        } => {
+            ensure_active(config.pause.withdraw, "withdraw")?;
+            ensure_deadline(&env, deadline)?;
+            let sent = must_pay(&info, rcpt.denom().as_str())?;
+            let (amount, withdrawn) = match exact {
+                Some(exact) => state.withdraw_exact(exact, sent)?,
+                None => (sent, state.withdraw(sent)?),
+            };
+            ensure_minimum(min_amount, withdrawn)?;
             state.save(deps.storage)?;
 
+            let mut response = Response::default();
+            if sent.gt(&amount) {
+                response = response.add_message(BankMsg::Send {
+                    to_address: info.sender.to_string(),
+                    amount: coins(sent.sub(amount).u128(), rcpt.denom()),
+                });
+            }
             match callback {
-                None => Response::default()
+                None => response
                     .add_message(rcpt.burn_msg(amount))
                     .add_message(BankMsg::Send {
                         to_address: info.sender.to_string(),
                         amount: coins(withdrawn.u128(), config.denom),
                     })
                     .add_event(event_withdraw(info.sender, withdrawn, amount)),
-                Some(cb) => Response::default()
+                Some(cb) => response
                     .add_message(rcpt.burn_msg(amount))
                     .add_message(cb.to_message(
                         &info.sender,
@@ -92,20 +143,179 @@ pub fn execute(
                     .add_event(event_withdraw(info.sender, withdrawn, amount)),
             }
         }
+        ExecuteMsg::QueueWithdraw {} => {
+            ensure_active(config.pause.withdraw, "withdraw")?;
+            let shares = must_pay(&info, rcpt.denom().as_str())?;
+            let ticket = Ticket::create(
+                deps.storage,
+                &mut state,
+                info.sender.clone(),
+                shares,
+                env.block.time,
+            )?;
+            state.save(deps.storage)?;
+            Response::default().add_event(event_withdraw_queue(info.sender, ticket.id, shares))
+        }
+        ExecuteMsg::ClaimWithdraw { id } => {
+            ensure_active(config.pause.withdraw, "withdraw")?;
+            let ticket = Ticket::load(deps.storage, id)?;
+            if ticket.owner != info.sender {
+                return Err(ContractError::Unauthorized {});
+            }
+            let amount = ticket.claim(deps.storage)?;
+            state.save(deps.storage)?;
+
+            let mut response =
+                Response::default().add_event(event_withdraw_claim(info.sender, id, amount));
+            if !amount.is_zero() {
+                response = response.add_message(BankMsg::Send {
+                    to_address: ticket.owner.to_string(),
+                    amount: coins(amount.u128(), &config.denom),
+                });
+            }
+            response
+        }
+        ExecuteMsg::CancelWithdraw { id } => {
+            let ticket = Ticket::load(deps.storage, id)?;
+            if ticket.owner != info.sender {
+                return Err(ContractError::Unauthorized {});
+            }
+            let shares = ticket.cancel(deps.storage, &mut state)?;
+            state.save(deps.storage)?;
+
+            Response::default()
+                .add_message(BankMsg::Send {
+                    to_address: ticket.owner.to_string(),
+                    amount: coins(shares.u128(), rcpt.denom()),
+                })
+                .add_event(event_withdraw_cancel(info.sender, id, shares))
+        }
+        ExecuteMsg::RepayFor { borrower, delegate } => {
+            let mut borrower = Borrower::load(deps.storage, deps.api.addr_validate(&borrower)?)?;
+            let amount = must_pay(&info, config.denom.as_str())?;
+            let premium = charge_premium(
+                deps.storage,
+                &env,
+                &config,
+                &mut state,
+                &mut borrower,
+                Response::default(),
+            )?;
+            let response = repay(
+                deps.branch(),
+                &info,
+                &mut state,
+                &mut borrower,
+                delegate,
+                amount,
+                Some(info.sender.clone()),
+            )?;
+            borrower.weigh(&mut state);
+            state.save(deps.storage)?;
+            premium
+                .add_submessages(response.messages)
+                .add_events(response.events)
+        }
+        ExecuteMsg::FlashLoan { amount, callback } => {
+            ensure_active(config.pause.flash_loan, "flash_loan")?;
+            let available = state.liquidity();
+            if amount.is_zero() || amount.gt(&available) {
+                return Err(ContractError::InsufficientLiquidity {
+                    available,
+                    requested: amount,
+                });
+            }
+            let loan = FlashLoan {
+                borrower: info.sender.clone(),
+                amount,
+                fee: config.flash_fee(amount)?,
+                balance: deps
+                    .querier
+                    .query_balance(env.contract.address.as_str(), &config.denom)?
+                    .amount,
+            };
+            loan.start(deps.storage)?;
+            state.save(deps.storage)?;
+
+            Response::default().add_submessage(SubMsg::reply_on_success(
+                callback.to_message(&info.sender, Empty {}, coins(amount.u128(), &config.denom))?,
+                FLASH_LOAN_REPLY,
+            ))
+        }
+        ExecuteMsg::Accrue {} => {
+            state.save(deps.storage)?;
+            Response::default()
+        }
+        ExecuteMsg::FundReserve {} => {
+            if info.sender != config.fee_address && config.guardian.as_ref() != Some(&info.sender) {
+                return Err(ContractError::Unauthorized {});
+            }
+            let amount = must_pay(&info, config.denom.as_str())?;
+            config.check_deposit_cap(state.deposit_pool.size().add(amount), Uint128::zero())?;
+            let shares = state.fund_reserve(amount)?;
+            state.save(deps.storage)?;
+            Response::default().add_event(event_fund_reserve(info.sender, amount, shares))
+        }
         ExecuteMsg::Market(market_msg) => {
             let mut borrower = Borrower::load(deps.storage, info.sender.clone())?;
-            execute_market(deps, info, &mut state, market_msg, &mut borrower)? //*define below, handles borrow and repay
+            // The premium is charged before the debt changes
+            let premium = charge_premium(
+                deps.storage,
+                &env,
+                &config,
+                &mut state,
+                &mut borrower,
+                Response::default(),
+            )?;
+            let response =
+                execute_market(deps.branch(), info, &mut state, market_msg, &mut borrower)?; //*define below, handles borrow and repay
+            premium
+                .add_submessages(response.messages)
+                .add_events(response.events)
+        }
+        ExecuteMsg::Guardian(guardian_msg) => {
+            if config.guardian.as_ref() != Some(&info.sender) {
+                return Err(ContractError::Unauthorized {});
+            }
+            state.save(deps.storage)?;
+            let pause = match guardian_msg {
+                GuardianMsg::Pause(pause) => config.pause.merge(&pause),
+                GuardianMsg::Shutdown {} => config.pause.merge(&Pause::shutdown()),
+            };
+            set_pause(deps.storage, config.clone(), pause)?
         }
     };
-    if fees.gt(&Uint128::zero()) {
-        response = response.add_message(rcpt.mint_msg(fees, config.fee_address.clone()));
+    if accrual.shares.gt(&Uint128::zero()) {
+        response = response.add_message(rcpt.mint_msg(accrual.shares, config.fee_address.clone()));
+    }
+    if let Some(event) = accrued {
+        response = response.add_event(event);
+    }
+
+    // Liquidity returned to the vault pays out queued withdrawals before it can be borrowed
+    let filled = fill(deps.storage, &mut state)?;
+    if !filled.is_empty() {
+        state.save(deps.storage)?;
+        let shares = filled
+            .iter()
+            .fold(Uint128::zero(), |acc, ticket| acc.add(ticket.shares));
+        response = response
+            .add_message(rcpt.burn_msg(shares))
+            .add_events(filled.into_iter().map(|ticket| {
+                event_withdraw_fill(
+                    ticket.owner,
+                    ticket.id,
+                    ticket.amount.unwrap_or_default(),
+                    ticket.shares,
+                )
+            }));
     }
 
     Ok(response)
 }
 
 pub fn execute_market(
-    deps: DepsMut,
+    mut deps: DepsMut,
     info: MessageInfo,
     state: &mut State,
     msg: MarketMsg,
@@ -117,84 +327,263 @@ pub fn execute_market(
             amount,
             callback,
             delegate,
+        } => borrow(
+            deps.branch(),
+            &info,
+            state,
+            borrower,
+            Loan::Amount(amount),
+            callback,
+            delegate,
+        )?,
+        MarketMsg::SetDelegateLimit { delegate, limit } => {
+            borrower.set_delegate_limit(deps.storage, deps.api.addr_validate(&delegate)?, limit)?;
+            Response::default().add_event(event_delegate_limit(
+                borrower.addr.clone(),
+                delegate,
+                limit,
+            ))
+        }
+        MarketMsg::TransferDebt {
+            from_delegate,
+            to_delegate,
+            shares,
         } => {
-            let shares = state.borrow(amount)?;
-            match delegate.clone() {
-                Some(d) => {
-                    borrower.delegate_borrow(
-                        deps.storage,
-                        deps.api.addr_validate(&d)?,
-                        &state.debt_pool,
-                        shares,
-                    )?;
-                }
-                None => {
-                    borrower.borrow(deps.storage, &state.debt_pool, shares)?;
-                }
-            };
-
-            match callback {
-                None => Response::default()
-                    .add_message(BankMsg::Send {
-                        to_address: info.sender.to_string(),
-                        amount: coins(amount.u128(), config.denom),
-                    })
-                    .add_event(event_borrow(
-                        borrower.addr.clone(),
-                        delegate,
-                        amount,
-                        shares,
-                    )),
-                Some(cb) => Response::default()
-                    .add_message(cb.to_message(
-                        &info.sender,
-                        Empty {},
-                        coins(amount.u128(), &config.denom),
-                    )?)
-                    .add_event(event_borrow(
-                        borrower.addr.clone(),
-                        delegate,
-                        amount,
-                        shares,
-                    )),
-            }
+            borrower.transfer(
+                deps.storage,
+                &state.debt_pool,
+                from_delegate
+                    .as_ref()
+                    .map(|d| deps.api.addr_validate(d))
+                    .transpose()?,
+                to_delegate
+                    .as_ref()
+                    .map(|d| deps.api.addr_validate(d))
+                    .transpose()?,
+                shares,
+            )?;
+            Response::default().add_event(event_transfer_debt(
+                borrower.addr.clone(),
+                from_delegate,
+                to_delegate,
+                shares,
+            ))
         }
+        MarketMsg::BorrowShares {
+            shares,
+            callback,
+            delegate,
+        } => borrow(
+            deps.branch(),
+            &info,
+            state,
+            borrower,
+            Loan::Shares(shares),
+            callback,
+            delegate,
+        )?,
         MarketMsg::Repay { delegate } => {
             let amount = must_pay(&info, config.denom.as_str())?;
-            let shares = state.repay(amount)?;
-            let delegate_address = delegate
-                .clone()
-                .map(|d| deps.api.addr_validate(&d))
-                .transpose()?;
-
-            let overpayment_shares = match delegate_address.clone() {
-                Some(d) => borrower.delegate_repay(deps.storage, d, shares),
-                None => borrower.repay(deps.storage, shares),
-            }?;
-
-            let overpayment_value = state.debt_pool.ownership(overpayment_shares);
-            let overpayment_receipient = delegate_address.unwrap_or(borrower.addr.clone());
-            let mut response = Response::default().add_event(event_repay(
-                borrower.addr.clone(),
+            repay(
+                deps.branch(),
+                &info,
+                state,
+                borrower,
                 delegate,
                 amount,
-                shares,
-            ));
-            if !overpayment_value.is_zero() {
+                None,
+            )?
+        }
+        MarketMsg::RepayShares { shares, delegate } => {
+            let paid = must_pay(&info, config.denom.as_str())?;
+            let outstanding = match &delegate {
+                Some(d) => borrower.delegate_shares(deps.storage, deps.api.addr_validate(d)?),
+                None => borrower.shares,
+            };
+            let amount = state.repay_value(min(shares, outstanding))?;
+            if paid.lt(&amount) {
+                return Err(ContractError::InsufficientRepay {
+                    debt: state.debt_pool.ownership(outstanding),
+                    value: amount,
+                    repaid: paid,
+                });
+            }
+            let mut response = repay(
+                deps.branch(),
+                &info,
+                state,
+                borrower,
+                delegate,
+                amount,
+                None,
+            )?;
+            if paid.gt(&amount) {
                 response = response.add_message(BankMsg::Send {
-                    to_address: overpayment_receipient.to_string(),
-                    amount: coins(overpayment_value.u128(), config.denom),
+                    to_address: info.sender.to_string(),
+                    amount: coins(paid.sub(amount).u128(), &config.denom),
                 });
             }
             response
         }
     };
+    // The borrower's premium was charged by the caller before its debt changed
+    borrower.weigh(state);
     state.save(deps.storage)?;
     Ok(response)
 }
 
+/// What a borrower asks for, either an amount or a number of debt shares
+enum Loan {
+    Amount(Uint128),
+    /// Exactly this many debt shares, for their value rounded down
+    Shares(Uint128),
+}
+
+fn borrow(
+    deps: DepsMut,
+    info: &MessageInfo,
+    state: &mut State,
+    borrower: &mut Borrower,
+    loan: Loan,
+    callback: Option<CallbackData>,
+    delegate: Option<String>,
+) -> Result<Response, ContractError> {
+    let config = Config::load(deps.storage)?;
+    ensure_active(config.pause.borrow, "borrow")?;
+    let (amount, shares) = match loan {
+        Loan::Amount(amount) => (amount, state.borrow(amount, config.max_utilization)?),
+        Loan::Shares(shares) => (state.borrow_shares(shares, config.max_utilization)?, shares),
+    };
+    match delegate.clone() {
+        Some(d) => {
+            borrower.delegate_borrow(deps.storage, deps.api.addr_validate(&d)?, state, shares)?;
+        }
+        None => {
+            borrower.borrow(deps.storage, state, shares)?;
+        }
+    };
+
+    Ok(match callback {
+        None => Response::default()
+            .add_message(BankMsg::Send {
+                to_address: info.sender.to_string(),
+                amount: coins(amount.u128(), config.denom),
+            })
+            .add_event(event_borrow(
+                borrower.addr.clone(),
+                delegate,
+                amount,
+                shares,
+            )),
+        Some(cb) => Response::default()
+            .add_message(cb.to_message(
+                &info.sender,
+                Empty {},
+                coins(amount.u128(), &config.denom),
+            )?)
+            .add_event(event_borrow(
+                borrower.addr.clone(),
+                delegate,
+                amount,
+                shares,
+            )),
+    })
+}
+
+/// Applies `amount` to the borrower's debt, or to one of its delegates. Overpayment is
+/// refunded to `refund`, defaulting to the obligation's owner
+fn repay(
+    deps: DepsMut,
+    info: &MessageInfo,
+    state: &mut State,
+    borrower: &mut Borrower,
+    delegate: Option<String>,
+    amount: Uint128,
+    refund: Option<Addr>,
+) -> Result<Response, ContractError> {
+    let config = Config::load(deps.storage)?;
+    ensure_active(config.pause.repay, "repay")?;
+    let delegate_address = delegate
+        .clone()
+        .map(|d| deps.api.addr_validate(&d))
+        .transpose()?;
+    // Only the debt owed is taken from the pool, the rest of the payment is refunded
+    let outstanding = match delegate_address.clone() {
+        Some(d) => borrower.delegate_shares(deps.storage, d),
+        None => borrower.shares,
+    };
+    let repaid = min(amount, state.repay_value(outstanding)?);
+    let shares = if repaid.is_zero() {
+        Uint128::zero()
+    } else {
+        state.repay(repaid)?
+    };
+
+    let overpayment_shares = match delegate_address.clone() {
+        Some(d) => borrower.delegate_repay(deps.storage, d, shares),
+        None => borrower.repay(deps.storage, shares),
+    }?;
+
+    let overpayment_value = amount
+        .sub(repaid)
+        .add(state.debt_pool.ownership(overpayment_shares));
+    let overpayment_receipient =
+        refund.unwrap_or(delegate_address.unwrap_or(borrower.addr.clone()));
+    let mut response = Response::default().add_event(event_repay(
+        borrower.addr.clone(),
+        delegate,
+        info.sender.clone(),
+        repaid,
+        shares,
+    ));
+    if !overpayment_value.is_zero() {
+        response = response.add_message(BankMsg::Send {
+            to_address: overpayment_receipient.to_string(),
+            amount: coins(overpayment_value.u128(), &config.denom),
+        });
+    }
+    Ok(response)
+}
+
+fn ensure_active(paused: bool, operation: &str) -> Result<(), ContractError> {
+    if paused {
+        return Err(ContractError::Paused {
+            operation: operation.to_string(),
+        });
+    }
+    Ok(())
+}
+
+fn ensure_deadline(env: &Env, deadline: Option<Timestamp>) -> Result<(), ContractError> {
+    match deadline {
+        Some(deadline) if env.block.time > deadline => {
+            Err(ContractError::DeadlineExceeded { deadline })
+        }
+        _ => Ok(()),
+    }
+}
+
+fn ensure_minimum(minimum: Option<Uint128>, actual: Uint128) -> Result<(), ContractError> {
+    match minimum {
+        Some(minimum) if actual < minimum => {
+            Err(ContractError::SlippageExceeded { minimum, actual })
+        }
+        _ => Ok(()),
+    }
+}
+
+fn set_pause(
+    storage: &mut dyn Storage,
+    mut config: Config,
+    pause: Pause,
+) -> Result<Response, ContractError> {
+    config.pause = pause;
+    config.save(storage)?;
+    Ok(Response::default().add_event(event_config("pause", to_json_string(&config.pause)?)))
+}
+
 #[cfg_attr(not(feature = "library"), entry_point)]
-pub fn sudo(deps: DepsMut, _env: Env, msg: SudoMsg) -> Result<Response, ContractError> {//*@audit-info admin function
+pub fn sudo(deps: DepsMut, env: Env, msg: SudoMsg) -> Result<Response, ContractError> {//*@audit-info admin function
     let mut config = Config::load(deps.storage)?;
 
     match msg {
@@ -202,30 +591,347 @@ pub fn sudo(deps: DepsMut, _env: Env, msg: SudoMsg) -> Result<Response, Contract
             Borrower::set(deps.storage, deps.api.addr_validate(&contract)?, limit)?;
             Ok(Response::default())
         }
+        SudoMsg::SetBorrowerDepositLimit { contract, limit } => {
+            let mut borrower = Borrower::load(deps.storage, deps.api.addr_validate(&contract)?)?;
+            if let Some(limit) = &limit {
+                limit.validate()?;
+            }
+            borrower.deposit_limit = limit;
+            borrower.save(deps.storage)?;
+            Ok(Response::default())
+        }
         SudoMsg::SetInterest(interest) => {//*updating interest rate
+            let response = accrue(deps.storage, &env, &mut config)?;
+            // A re-tuned adaptive model keeps the rate it has learned, within its new bounds.
+            // A new adaptive model starts from its own initial rate
             interest.validate()?;
+            let mut state = State::load(deps.storage)?;
+            state.rate_at_target = match (&config.interest, &interest) {
+                (InterestModel::Adaptive(from), InterestModel::Adaptive(to)) => Some(
+                    state
+                        .rate_at_target
+                        .unwrap_or(from.initial_rate)
+                        .clamp(to.min_rate, to.max_rate),
+                ),
+                _ => None,
+            };
             config.interest = interest;
+            config.ramp = None;
+            config.validate()?;
             config.save(deps.storage)?;
-            Ok(Response::default())
+            state.save(deps.storage)?;
+            Ok(response.add_event(event_config("interest", to_json_string(&config.interest)?)))
+        }
+        SudoMsg::RampInterest { interest, duration } => {
+            if duration == 0 {
+                return Err(ContractError::Invalid("duration".to_string()));
+            }
+            if let Some(ramp) = config.ramp.as_ref().filter(|x| x.end > env.block.time) {
+                return Err(ContractError::Invalid(format!(
+                    "ramp in progress until {}",
+                    ramp.end
+                )));
+            }
+            let response = accrue(deps.storage, &env, &mut config)?;
+            let mut state = State::load(deps.storage)?;
+            // Freeze an adaptive curve where it stands, the new model adapts from its own
+            // initial rate
+            let from = match config.interest {
+                InterestModel::Adaptive(x) => {
+                    InterestModel::Kink(x.curve(state.rate_at_target.unwrap_or(x.initial_rate)))
+                }
+                x => x,
+            };
+            config.interest = interest;
+            config.ramp = Some(Ramp {
+                from,
+                start: env.block.time,
+                end: env.block.time.plus_seconds(duration),
+            });
+            config.validate()?;
+            config.save(deps.storage)?;
+            state.rate_at_target = None;
+            state.save(deps.storage)?;
+            Ok(response.add_event(
+                event_config("interest", to_json_string(&config.interest)?)
+                    .add_attribute("ramp", to_json_string(&config.ramp)?),
+            ))
+        }
+        SudoMsg::SetFee(fee) => {
+            // Settle the interest accrued so far at the current fee
+            let response = accrue(deps.storage, &env, &mut config)?;
+            config.fee = fee;
+            config.validate()?;
+            config.save(deps.storage)?;
+            Ok(response.add_event(event_config("fee", fee.to_string())))
+        }
+        SudoMsg::SetFeeAddress(fee_address) => {
+            // Fees accrued so far are owed to the current fee address
+            let response = accrue(deps.storage, &env, &mut config)?;
+            config.fee_address = deps.api.addr_validate(&fee_address)?;
+            config.validate()?;
+            config.save(deps.storage)?;
+            Ok(response.add_event(event_config("fee_address", fee_address)))
+        }
+        SudoMsg::SetReceiptMetadata(metadata) => {
+            let rcpt = TokenFactory::new(&env, format!("ghost-vault/{}", config.denom).as_str());
+            Ok(Response::default()
+                .add_message(rcpt.set_metadata_msg(metadata.clone()))
+                .add_event(event_config("receipt", to_json_string(&metadata)?)))
+        }
+        SudoMsg::SetPause(pause) => set_pause(deps.storage, config, pause),
+        SudoMsg::SetGuardian(guardian) => {
+            config.guardian = guardian
+                .as_ref()
+                .map(|x| deps.api.addr_validate(x))
+                .transpose()?;
+            config.save(deps.storage)?;
+            Ok(Response::default()
+                .add_event(event_config("guardian", guardian.unwrap_or_default())))
+        }
+        SudoMsg::SetDepositCap { total, depositor } => {
+            config.deposit_cap = total;
+            config.depositor_cap = depositor;
+            config.save(deps.storage)?;
+            Ok(Response::default().add_event(
+                event_config("deposit_cap", total.unwrap_or_default())
+                    .add_attribute("depositor_cap", depositor.unwrap_or_default()),
+            ))
+        }
+        SudoMsg::SetMaxUtilization(max_utilization) => {
+            config.max_utilization = max_utilization;
+            config.validate()?;
+            config.save(deps.storage)?;
+            Ok(Response::default()
+                .add_event(event_config("max_utilization", max_utilization.to_string())))
+        }
+        SudoMsg::SetFlashFee(flash_fee) => {
+            config.flash_fee = flash_fee;
+            config.validate()?;
+            config.save(deps.storage)?;
+            Ok(Response::default().add_event(event_config("flash_fee", flash_fee.to_string())))
+        }
+        SudoMsg::SetFlashLimit(flash_limit) => {
+            config.flash_limit = flash_limit;
+            config.save(deps.storage)?;
+            Ok(Response::default()
+                .add_event(event_config("flash_limit", flash_limit.unwrap_or_default())))
+        }
+        SudoMsg::WriteOff {
+            borrower,
+            delegate,
+            shares,
+        } => {
+            // Interest up to this block is still owed on the debt being written off
+            let response = accrue(deps.storage, &env, &mut config)?;
+            let mut state = State::load(deps.storage)?;
+            let mut borrower = Borrower::load(deps.storage, deps.api.addr_validate(&borrower)?)?;
+            let response = charge_premium(
+                deps.storage,
+                &env,
+                &config,
+                &mut state,
+                &mut borrower,
+                response,
+            )?;
+            let shares = borrower.write_off(
+                deps.storage,
+                delegate
+                    .as_ref()
+                    .map(|d| deps.api.addr_validate(d))
+                    .transpose()?,
+                shares,
+            )?;
+            borrower.weigh(&mut state);
+            let (amount, reserve) = state.write_off(shares)?;
+            state.save(deps.storage)?;
+            Ok(response.add_event(event_write_off(
+                borrower.addr,
+                delegate,
+                amount,
+                shares,
+                reserve,
+            )))
+        }
+        SudoMsg::MigrateBorrowerDebt { from, to } => {
+            if from == to {
+                return Err(ContractError::Invalid("to".to_string()));
+            }
+            // The destination's limit is checked against the debt's current value
+            let response = accrue(deps.storage, &env, &mut config)?;
+            let mut state = State::load(deps.storage)?;
+            let mut from = Borrower::load(deps.storage, deps.api.addr_validate(&from)?)?;
+            let mut to = Borrower::load(deps.storage, deps.api.addr_validate(&to)?)?;
+            let response =
+                charge_premium(deps.storage, &env, &config, &mut state, &mut from, response)?;
+            let response =
+                charge_premium(deps.storage, &env, &config, &mut state, &mut to, response)?;
+            let shares = from.transfer_all(deps.storage, &state, &mut to)?;
+            from.weigh(&mut state);
+            to.weigh(&mut state);
+            state.save(deps.storage)?;
+            Ok(response.add_event(event_migrate_debt(from.addr, to.addr, shares)))
+        }
+        SudoMsg::SetBorrowerStatus { contract, status } => {
+            // Settle the premium owed so far at the current status
+            let response = accrue(deps.storage, &env, &mut config)?;
+            let mut state = State::load(deps.storage)?;
+            let mut borrower = Borrower::load(deps.storage, deps.api.addr_validate(&contract)?)?;
+            let response = charge_premium(
+                deps.storage,
+                &env,
+                &config,
+                &mut state,
+                &mut borrower,
+                response,
+            )?;
+            borrower.status = status;
+            borrower.weigh(&mut state);
+            borrower.save(deps.storage)?;
+            state.save(deps.storage)?;
+            Ok(response.add_event(event_borrower_status(borrower.addr, &borrower.status)))
+        }
+        SudoMsg::SetBorrowerSpread { contract, spread } => {
+            // Settle the spread owed so far at the current rate
+            let response = accrue(deps.storage, &env, &mut config)?;
+            let mut state = State::load(deps.storage)?;
+            let mut borrower = Borrower::load(deps.storage, deps.api.addr_validate(&contract)?)?;
+            let response = charge_premium(
+                deps.storage,
+                &env,
+                &config,
+                &mut state,
+                &mut borrower,
+                response,
+            )?;
+            borrower.spread = spread;
+            borrower.weigh(&mut state);
+            borrower.save(deps.storage)?;
+            state.save(deps.storage)?;
+            Ok(response.add_event(event_borrower_spread(borrower.addr, spread)))
+        }
+        SudoMsg::RemoveBorrower { contract } => {
+            let borrower = Borrower::load(deps.storage, deps.api.addr_validate(&contract)?)?;
+            borrower.remove(deps.storage)?;
+            Ok(Response::default().add_event(event_remove_borrower(borrower.addr)))
+        }
+        SudoMsg::SetCompounding(compounding) => {
+            let response = accrue(deps.storage, &env, &mut config)?;
+            config.compounding = compounding;
+            config.save(deps.storage)?;
+            Ok(response.add_event(event_config("compounding", compounding.to_string())))
         }
+        SudoMsg::SetReserveFraction(reserve_fraction) => {
+            // Settle the fees accrued so far at the current fraction
+            let response = accrue(deps.storage, &env, &mut config)?;
+            config.reserve_fraction = reserve_fraction;
+            config.validate()?;
+            config.save(deps.storage)?;
+            Ok(response.add_event(event_config(
+                "reserve_fraction",
+                reserve_fraction.to_string(),
+            )))
+        }
+        SudoMsg::SweepReserve { shares, to } => {
+            let to = deps.api.addr_validate(&to)?;
+            let mut response = accrue(deps.storage, &env, &mut config)?;
+            let mut state = State::load(deps.storage)?;
+            let shares = state.sweep_reserve(shares)?;
+            state.save(deps.storage)?;
+
+            if !shares.is_zero() {
+                let rcpt =
+                    TokenFactory::new(&env, format!("ghost-vault/{}", config.denom).as_str());
+                response = response.add_message(rcpt.mint_msg(shares, to.clone()));
+            }
+            Ok(response.add_event(event_sweep_reserve(to, shares)))
+        }
+    }
+}
+
+/// Distributes interest up to the current block under the current config, minting the
+/// protocol fee to the current fee address
+fn accrue(
+    storage: &mut dyn Storage,
+    env: &Env,
+    config: &mut Config,
+) -> Result<Response, ContractError> {
+    let mut state = State::load(storage)?;
+    let rcpt = TokenFactory::new(env, format!("ghost-vault/{}", config.denom).as_str());
+    let accrual = state.distribute_interest(env, config)?;
+    state.save(storage)?;
+    if config.end_ramp(env.block.time) {
+        config.save(storage)?;
+    }
+
+    let mut response = Response::default();
+    if accrual.shares.gt(&Uint128::zero()) {
+        response = response.add_message(rcpt.mint_msg(accrual.shares, config.fee_address.clone()));
+    }
+    if let Some(event) = event_accrual(&accrual, &state) {
+        response = response.add_event(event);
+    }
+    Ok(response)
+}
+
+/// Allocates the borrower's premium up to the current block to its debt, minting the protocol's
+/// cut of any part not yet charged to the fee address. The borrower is saved, and its premium
+/// leaves the vault's premium rate until `Borrower::weigh`; the caller saves `state`
+fn charge_premium(
+    storage: &mut dyn Storage,
+    env: &Env,
+    config: &Config,
+    state: &mut State,
+    borrower: &mut Borrower,
+    mut response: Response,
+) -> Result<Response, ContractError> {
+    let (amount, shares) = borrower.accrue_premium(state, config, env.block.time)?;
+    borrower.save(storage)?;
+    if !shares.is_zero() {
+        let rcpt = TokenFactory::new(env, format!("ghost-vault/{}", config.denom).as_str());
+        response = response.add_message(rcpt.mint_msg(shares, config.fee_address.clone()));
     }
+    if !amount.is_zero() {
+        response = response.add_event(event_premium(borrower.addr.clone(), amount, shares));
+    }
+    Ok(response)
+}
+
+/// Reports any accrual over a non-zero period
+fn event_accrual(accrual: &Accrual, state: &State) -> Option<Event> {
+    (accrual.seconds > 0)
+        .then(|| event_accrue(accrual, state.debt_pool.ratio(), state.deposit_pool.ratio()))
 }
 
 #[cfg_attr(not(feature = "library"), entry_point)]
 pub fn query(deps: Deps, env: Env, msg: QueryMsg) -> Result<Binary, ContractError> {
     let mut state = State::load(deps.storage)?;
-    let config = Config::load(deps.storage)?;
+    let mut config = Config::load(deps.storage)?;
     state.distribute_interest(&env, &config)?;
+    config.end_ramp(env.block.time);
 
     match msg {
         QueryMsg::Config {} => Ok(to_json_binary(&ConfigResponse {
             denom: config.denom,
             interest: config.interest,
         })?),
+        QueryMsg::Settings {} => Ok(to_json_binary(&SettingsResponse {
+            fee: config.fee,
+            fee_address: config.fee_address.to_string(),
+            guardian: config.guardian.map(|x| x.to_string()),
+            pause: config.pause,
+            deposit_cap: config.deposit_cap,
+            depositor_cap: config.depositor_cap,
+            max_utilization: config.max_utilization,
+            reserve_fraction: config.reserve_fraction,
+            compounding: config.compounding,
+            flash_fee: config.flash_fee,
+            flash_limit: config.flash_limit,
+        })?),
 
         QueryMsg::Status {} => Ok(to_json_binary(&StatusResponse {
-            debt_rate: state.debt_rate(&config.interest)?,
-            lend_rate: state.lend_rate(&config.interest)?,
+            debt_rate: state.debt_rate(&config.interest, config.ramp.as_ref())?,
+            lend_rate: state.lend_rate(&config.interest, config.ramp.as_ref())?,
             utilization_ratio: state.utilization(),
             last_updated: state.last_updated,
             debt_pool: PoolResponse {
@@ -239,93 +945,428 @@ pub fn query(deps: Deps, env: Env, msg: QueryMsg) -> Result<Binary, ContractErro
                 ratio: state.deposit_pool.ratio(),
             },
         })?),
+        QueryMsg::Interest {} => Ok(to_json_binary(&InterestResponse {
+            rate_at_target: config.interest.rate_at_target(state.rate_at_target),
+            ramp: config
+                .ramp
+                .map(|ramp| -> StdResult<RampResponse> {
+                    Ok(RampResponse {
+                        from_rate: ramp.from.rate(state.utilization(), None)?,
+                        to_rate: config
+                            .interest
+                            .rate(state.utilization(), state.rate_at_target)?,
+                        progress: ramp.progress(state.last_updated),
+                        from: ramp.from,
+                        to: config.interest.clone(),
+                        start: ramp.start,
+                        end: ramp.end,
+                    })
+                })
+                .transpose()?,
+            interest: config.interest,
+        })?),
         QueryMsg::Borrower { addr } => {
             let borrower = Borrower::load(deps.storage, deps.api.addr_validate(&addr)?)?;
-            let current = state.debt_pool.ownership(borrower.shares);
-            Ok(to_json_binary(&BorrowerResponse {
+            let (state, borrower) = settle(&env, &config, &state, borrower)?;
+            Ok(to_json_binary(&borrower_response(
+                &state, &config, &borrower,
+            )?)?)
+        }
+        QueryMsg::BorrowerTerms { addr } => {
+            let borrower = Borrower::load(deps.storage, deps.api.addr_validate(&addr)?)?;
+            Ok(to_json_binary(&BorrowerTermsResponse {
                 addr: borrower.addr.to_string(),
-                denom: config.denom,
-                limit: borrower.limit,
-                current,
-                shares: borrower.shares,
-                available: min(
-                    // Current borrows can exceed limit due to interest
-                    borrower.limit.checked_sub(current).unwrap_or_default(),
-                    state.deposit_pool.size() - state.debt_pool.size(),
-                ),
+                deposit_limit: borrower.deposit_limit.clone(),
+                rate: state
+                    .debt_rate(&config.interest, config.ramp.as_ref())?
+                    .checked_add(borrower.premium())?,
+                spread: borrower.spread,
+                status: borrower.status,
             })?)
         }
         QueryMsg::Delegate { borrower, addr } => {
             let borrower = Borrower::load(deps.storage, deps.api.addr_validate(&borrower)?)?;
+            let (state, borrower) = settle(&env, &config, &state, borrower)?;
             let delegate = borrower.delegate_shares(deps.storage, deps.api.addr_validate(&addr)?);
-            let current = state.debt_pool.ownership(borrower.shares);
 
             Ok(to_json_binary(&DelegateResponse {
-                borrower: BorrowerResponse {
-                    addr: borrower.addr.to_string(),
-                    denom: config.denom,
-                    limit: borrower.limit,
-                    current,
-                    shares: borrower.shares,
-                    available: min(
-                        borrower.limit.checked_sub(current).unwrap_or_default(),
-                        state.deposit_pool.size() - state.debt_pool.size(),
-                    ),
-                },
+                borrower: borrower_response(&state, &config, &borrower)?,
                 addr,
                 current: state.debt_pool.ownership(delegate),
                 shares: delegate,
             })?)
         }
-        QueryMsg::Borrowers { limit, start_after } => {
-            let borrowers = Borrower::list(
-                deps.storage,
-                limit,
+        QueryMsg::Delegates {
+            borrower,
+            limit,
+            start_after,
+        } => {
+            let borrower = Borrower::load(deps.storage, deps.api.addr_validate(&borrower)?)?;
+            let (state, borrower) = settle(&env, &config, &state, borrower)?;
+            let delegates = borrower
+                .delegates(
+                    deps.storage,
+                    limit,
+                    start_after
+                        .map(|x| deps.api.addr_validate(x.as_str()))
+                        .transpose()?,
+                )
+                .map(|x| {
+                    x.map(|(addr, shares)| DelegateDebtResponse {
+                        addr: addr.to_string(),
+                        current: state.debt_pool.ownership(shares),
+                        shares,
+                        limit: borrower.delegate_limit(deps.storage, addr),
+                    })
+                })
+                .collect::<StdResult<Vec<DelegateDebtResponse>>>()?;
+            let delegated_shares = borrower.delegated_shares(deps.storage)?;
+            let undelegated_shares = borrower.undelegated_shares(deps.storage)?;
+            Ok(to_json_binary(&DelegatesResponse {
+                borrower: borrower_response(&state, &config, &borrower)?,
+                delegates,
+                delegated_shares,
+                delegated: state.debt_pool.ownership(delegated_shares),
+                undelegated_shares,
+                undelegated: state.debt_pool.ownership(undelegated_shares),
+            })?)
+        }
+        QueryMsg::Borrowers { limit, start_after } => {
+            let borrowers = Borrower::list(
+                deps.storage,
+                limit,
                 start_after
                     .map(|x| deps.api.addr_validate(x.as_str()))
                     .transpose()?,
             )
             .map(|x| {
-                x.map(|borrower| {
-                    let current = state.debt_pool.ownership(borrower.shares);
-
-                    BorrowerResponse {
-                        addr: borrower.addr.to_string(),
-                        denom: config.denom.clone(),
-                        limit: borrower.limit,
-                        current,
-                        shares: borrower.shares,
-                        available: min(
-                            // Current borrows can exceed limit due to interest
-                            borrower.limit.checked_sub(current).unwrap_or_default(),
-                            state.deposit_pool.size() - state.debt_pool.size(),
-                        ),
-                    }
-                })
+                let (state, borrower) = settle(&env, &config, &state, x?)?;
+                borrower_response(&state, &config, &borrower)
             })
-            .collect::<StdResult<Vec<BorrowerResponse>>>()?;
+            .collect::<Result<Vec<BorrowerResponse>, ContractError>>()?;
             Ok(to_json_binary(&BorrowersResponse { borrowers })?)
         }
+        QueryMsg::WithdrawalQueue { limit, start_after } => {
+            let tickets = Ticket::queued(deps.storage, limit, start_after)
+                .map(|x| x.map(|ticket| ticket_response(&state, ticket)))
+                .collect::<StdResult<Vec<TicketResponse>>>()?;
+            Ok(to_json_binary(&WithdrawalQueueResponse {
+                shares: state.queued_shares,
+                value: state.deposit_pool.ownership(state.queued_shares),
+                tickets,
+            })?)
+        }
+        QueryMsg::Reserve {} => Ok(to_json_binary(&ReserveResponse {
+            fraction: config.reserve_fraction,
+            shares: state.reserve_shares,
+            value: state.deposit_pool.ownership(state.reserve_shares),
+        })?),
+        QueryMsg::WithdrawalTicket { id } => Ok(to_json_binary(&ticket_response(
+            &state,
+            Ticket::load(deps.storage, id)?,
+        ))?),
+        QueryMsg::PreviewDeposit { amount } => {
+            let shares = state.deposit(amount)?;
+            Ok(to_json_binary(&preview(&state, &config, shares, amount)?)?)
+        }
+        QueryMsg::PreviewWithdraw { shares } => {
+            let amount = state.withdraw(shares)?;
+            Ok(to_json_binary(&preview(&state, &config, shares, amount)?)?)
+        }
+        QueryMsg::PreviewBorrow { borrower, amount } => {
+            let borrower = Borrower::load(deps.storage, deps.api.addr_validate(&borrower)?)?;
+            let (mut state, mut borrower) = settle(&env, &config, &state, borrower)?;
+            let shares = state.borrow(amount, config.max_utilization)?;
+            borrower.check_limit(&state, shares)?;
+            // The new debt pays the borrower's premium too
+            borrower.shares += shares;
+            borrower.weigh(&mut state);
+            Ok(to_json_binary(&preview(&state, &config, shares, amount)?)?)
+        }
+        QueryMsg::PreviewRepay { borrower, amount } => {
+            let borrower = Borrower::load(deps.storage, deps.api.addr_validate(&borrower)?)?;
+            let (mut state, mut borrower) = settle(&env, &config, &state, borrower)?;
+            // Payment beyond the borrower's debt is refunded
+            let repaid = min(amount, state.repay_value(borrower.shares)?);
+            let shares = if repaid.is_zero() {
+                Uint128::zero()
+            } else {
+                state.repay(repaid)?
+            };
+            borrower.shares -= shares;
+            borrower.weigh(&mut state);
+            Ok(to_json_binary(&preview(&state, &config, shares, repaid)?)?)
+        }
+        QueryMsg::MaxWithdraw { addr } => {
+            let rcpt = TokenFactory::new(&env, format!("ghost-vault/{}", config.denom).as_str());
+            let held = deps.querier.query_balance(addr, rcpt.denom())?.amount;
+            let mut shares = Uint128::zero();
+            let mut amount = Uint128::zero();
+            if !config.pause.withdraw && !held.is_zero() {
+                // Capped by the liquidity available, rounding the shares down. A pool written
+                // down to nothing has nothing to withdraw
+                let available = min(state.deposit_pool.ownership(held), state.liquidity());
+                shares = min(
+                    held,
+                    available
+                        .checked_multiply_ratio(
+                            state.deposit_pool.shares(),
+                            state.deposit_pool.size(),
+                        )
+                        .unwrap_or_default(),
+                );
+                if !shares.is_zero() {
+                    amount = state.withdraw(shares)?;
+                }
+            }
+            Ok(to_json_binary(&preview(&state, &config, shares, amount)?)?)
+        }
+        QueryMsg::MaxBorrow { borrower } => {
+            let borrower = Borrower::load(deps.storage, deps.api.addr_validate(&borrower)?)?;
+            let (mut state, mut borrower) = settle(&env, &config, &state, borrower)?;
+            let mut shares = Uint128::zero();
+            let mut amount = Uint128::zero();
+            if !config.pause.borrow && borrower.status == BorrowerStatus::Active {
+                amount = min(
+                    // Current borrows can exceed limit due to interest
+                    borrower
+                        .effective_limit(&state)
+                        .checked_sub(state.debt_pool.ownership(borrower.shares))
+                        .unwrap_or_default(),
+                    state.borrowable(config.max_utilization),
+                );
+                if !amount.is_zero() {
+                    shares = state.borrow(amount, config.max_utilization)?;
+                }
+            }
+            borrower.shares += shares;
+            borrower.weigh(&mut state);
+            Ok(to_json_binary(&preview(&state, &config, shares, amount)?)?)
+        }
+        QueryMsg::Position { addr } => Ok(to_json_binary(&position_response(
+            deps, &env, &config, &state, addr,
+        )?)?),
+        QueryMsg::Positions { addrs } => {
+            let positions = addrs
+                .into_iter()
+                .map(|addr| position_response(deps, &env, &config, &state, addr))
+                .collect::<Result<Vec<PositionResponse>, ContractError>>()?;
+            Ok(to_json_binary(&PositionsResponse { positions })?)
+        }
+    }
+}
+
+/// Allocates the borrower's premium up to the current block against a copy of the state. Its
+/// premium is left out of the copy's premium rate, for the caller to `weigh` once its simulated
+/// debt has changed
+fn settle(
+    env: &Env,
+    config: &Config,
+    state: &State,
+    mut borrower: Borrower,
+) -> Result<(State, Borrower), ContractError> {
+    let mut state = state.clone();
+    borrower.accrue_premium(&mut state, config, env.block.time)?;
+    Ok((state, borrower))
+}
+
+fn borrower_response(
+    state: &State,
+    config: &Config,
+    borrower: &Borrower,
+) -> Result<BorrowerResponse, ContractError> {
+    let current = state.debt_pool.ownership(borrower.shares);
+    let limit = borrower.effective_limit(state);
+    Ok(BorrowerResponse {
+        addr: borrower.addr.to_string(),
+        denom: config.denom.clone(),
+        limit,
+        current,
+        shares: borrower.shares,
+        // Borrowing is closed to a borrower winding down
+        available: match borrower.status {
+            BorrowerStatus::Active => min(
+                // Current borrows can exceed limit due to interest
+                limit.checked_sub(current).unwrap_or_default(),
+                state.borrowable(config.max_utilization),
+            ),
+            BorrowerStatus::WindDown { .. } => Uint128::zero(),
+        },
+    })
+}
+
+fn position_response(
+    deps: Deps,
+    env: &Env,
+    config: &Config,
+    state: &State,
+    addr: String,
+) -> Result<PositionResponse, ContractError> {
+    let rcpt = TokenFactory::new(env, format!("ghost-vault/{}", config.denom).as_str());
+    let addr = deps.api.addr_validate(&addr)?;
+    let shares = deps.querier.query_balance(&addr, rcpt.denom())?.amount;
+    let pool_share = if state.deposit_pool.shares().is_zero() {
+        Decimal::zero()
+    } else {
+        Decimal::from_ratio(shares, state.deposit_pool.shares())
+    };
+    Ok(PositionResponse {
+        addr: addr.to_string(),
+        shares,
+        value: state.deposit_pool.ownership(shares),
+        pool_share,
+        lend_rate: state.lend_rate(&config.interest, config.ramp.as_ref())?,
+    })
+}
+
+/// Reports a simulated action against the state it leaves the vault in
+fn preview(
+    state: &State,
+    config: &Config,
+    shares: Uint128,
+    amount: Uint128,
+) -> StdResult<PreviewResponse> {
+    Ok(PreviewResponse {
+        shares,
+        amount,
+        utilization_ratio: state.utilization(),
+        debt_rate: state.debt_rate(&config.interest, config.ramp.as_ref())?,
+        lend_rate: state.lend_rate(&config.interest, config.ramp.as_ref())?,
+    })
+}
+
+fn ticket_response(state: &State, ticket: Ticket) -> TicketResponse {
+    TicketResponse {
+        id: ticket.id,
+        owner: ticket.owner.to_string(),
+        shares: ticket.shares,
+        amount: ticket
+            .amount
+            .unwrap_or(state.deposit_pool.ownership(ticket.shares)),
+        filled: ticket.amount.is_some(),
+        created: ticket.created,
+    }
+}
+
+#[cfg_attr(not(feature = "library"), entry_point)]
+pub fn reply(deps: DepsMut, env: Env, msg: Reply) -> Result<Response, ContractError> {
+    match msg.id {
+        FLASH_LOAN_REPLY => {
+            let loan = FlashLoan::finish(deps.storage)?;
+            let config = Config::load(deps.storage)?;
+            let balance = deps
+                .querier
+                .query_balance(env.contract.address.as_str(), &config.denom)?
+                .amount;
+            if balance.lt(&loan.expected()) {
+                return Err(ContractError::FlashLoanUnpaid {
+                    expected: loan.expected(),
+                    balance,
+                });
+            }
+
+            let mut state = State::load(deps.storage)?;
+            let shares = state.credit(loan.fee, &config)?;
+            state.save(deps.storage)?;
+
+            let rcpt = TokenFactory::new(&env, format!("ghost-vault/{}", config.denom).as_str());
+            let mut response = Response::default().add_event(event_flash_loan(
+                loan.borrower,
+                loan.amount,
+                loan.fee,
+            ));
+            if !shares.is_zero() {
+                response = response.add_message(rcpt.mint_msg(shares, config.fee_address));
+            }
+            Ok(response)
+        }
+        id => Err(ContractError::Invalid(format!("reply id {id}"))),
     }
 }
 
 #[cfg_attr(not(feature = "library"), entry_point)]
 pub fn migrate(deps: DepsMut, _env: Env, _msg: ()) -> Result<Response, ContractError> {
+    let from = parse_version(&get_contract_version(deps.storage)?.version)?;
+    if from > parse_version(CONTRACT_VERSION)? {
+        return Err(ContractError::Invalid("version".to_string()));
+    }
+    // Delegates were stored alongside their borrower before 1.0.2
+    if from < (1, 0, 2) {
+        crate::borrowers::migrate(deps.storage)?;
+    }
+    // 1.1.0 splits borrower debt into units. Config and state only gain fields with defaults,
+    // so they are rewritten in the new format
+    if from < (1, 1, 0) {
+        crate::borrowers::migrate_units(deps.storage)?;
+        Config::load(deps.storage)?.save(deps.storage)?;
+        State::load(deps.storage)?.save(deps.storage)?;
+    }
     set_contract_version(deps.storage, CONTRACT_NAME, CONTRACT_VERSION)?;
-    crate::borrowers::migrate(deps.storage)?;
     Ok(Response::default())
 }
 
+fn parse_version(version: &str) -> Result<(u64, u64, u64), ContractError> {
+    let parts = version
+        .split('.')
+        .map(|x| x.parse::<u64>())
+        .collect::<Result<Vec<u64>, _>>()
+        .map_err(|_| ContractError::Invalid("version".to_string()))?;
+    match parts[..] {
+        [major, minor, patch] => Ok((major, minor, patch)),
+        _ => Err(ContractError::Invalid("version".to_string())),
+    }
+}
+
 #[cfg(all(test, feature = "mock"))]
 mod tests {
 
     use std::str::FromStr;
 
     use super::*;
-    use cosmwasm_std::{coin, Decimal, Event, Uint128};
+    use crate::borrowers::DepositLimit;
+    use crate::interest::{Adaptive, Fixed, Piecewise};
+    use crate::mock::{FlashReceiver, GhostVault};
+    use cosmwasm_std::{coin, Addr, Decimal, Event, Uint128};
     use cw_multi_test::{ContractWrapper, Executor};
     use rujira_rs::{ghost::vault::Interest, TokenMetadata};
-    use rujira_rs_testing::mock_rujira_app;
+    use rujira_rs_testing::{mock_rujira_app, RujiraApp};
+
+    fn setup(app: &mut RujiraApp, fee: Decimal, fee_address: &Addr) -> Addr {
+        let owner = app.api().addr_make("owner");
+        let code = Box::new(
+            ContractWrapper::new(execute, instantiate, query)
+                .with_sudo(sudo)
+                .with_reply(reply),
+        );
+        let code_id = app.store_code(code);
+        app.instantiate_contract(
+            code_id,
+            owner,
+            &InstantiateMsg {
+                denom: "btc".to_string(),
+                receipt: TokenMetadata {
+                    description: "".to_string(),
+                    display: "".to_string(),
+                    name: "".to_string(),
+                    symbol: "".to_string(),
+                    uri: None,
+                    uri_hash: None,
+                },
+                interest: InterestModel::Kink(Interest {
+                    target_utilization: Decimal::from_ratio(8u128, 10u128),
+                    base_rate: Decimal::from_ratio(1u128, 10u128),
+                    step1: Decimal::from_ratio(1u128, 10u128),
+                    step2: Decimal::from_ratio(3u128, 1u128),
+                }),
+                fee,
+                fee_address: fee_address.to_string(),
+            },
+            &[],
+            "template",
+            None,
+        )
+        .unwrap()
+    }
 
     #[test]
     fn lifecycle() {
@@ -344,7 +1385,11 @@ mod tests {
                 .unwrap();
         });
 
-        let code = Box::new(ContractWrapper::new(execute, instantiate, query).with_sudo(sudo));
+        let code = Box::new(
+            ContractWrapper::new(execute, instantiate, query)
+                .with_sudo(sudo)
+                .with_reply(reply),
+        );
         let code_id = app.store_code(code);
         let contract = app
             .instantiate_contract(
@@ -360,12 +1405,12 @@ mod tests {
                         uri: None,
                         uri_hash: None,
                     },
-                    interest: Interest {
+                    interest: InterestModel::Kink(Interest {
                         target_utilization: Decimal::from_ratio(8u128, 10u128),
                         base_rate: Decimal::from_ratio(1u128, 10u128),
                         step1: Decimal::from_ratio(1u128, 10u128),
                         step2: Decimal::from_ratio(3u128, 1u128),
-                    },
+                    }),
                     fee: Decimal::zero(),
                     fee_address: owner.to_string(),
                 },
@@ -380,7 +1425,11 @@ mod tests {
             .execute_contract(
                 owner.clone(),
                 contract.clone(),
-                &ExecuteMsg::Deposit { callback: None },
+                &ExecuteMsg::Deposit {
+                    callback: None,
+                    min_shares: None,
+                    deadline: None,
+                },
                 &coins(1_000u128, "btc"),
             )
             .unwrap();
@@ -405,7 +1454,12 @@ mod tests {
             .execute_contract(
                 owner.clone(),
                 contract.clone(),
-                &ExecuteMsg::Withdraw { callback: None },
+                &ExecuteMsg::Withdraw {
+                    callback: None,
+                    amount: None,
+                    min_amount: None,
+                    deadline: None,
+                },
                 &coins(200u128, "x/ghost-vault/btc"),
             )
             .unwrap();
@@ -530,7 +1584,11 @@ mod tests {
             .execute_contract(
                 owner.clone(),
                 contract.clone(),
-                &ExecuteMsg::Deposit { callback: None },
+                &ExecuteMsg::Deposit {
+                    callback: None,
+                    min_shares: None,
+                    deadline: None,
+                },
                 &coins(1_000u128, "btc"),
             )
             .unwrap();
@@ -575,7 +1633,12 @@ mod tests {
             .execute_contract(
                 owner.clone(),
                 contract.clone(),
-                &ExecuteMsg::Withdraw { callback: None },
+                &ExecuteMsg::Withdraw {
+                    callback: None,
+                    amount: None,
+                    min_amount: None,
+                    deadline: None,
+                },
                 &coins(200u128, "x/ghost-vault/btc"),
             )
             .unwrap();
@@ -619,4 +1682,3195 @@ mod tests {
             Decimal::from_str("1.020253164556962025").unwrap()
         );
     }
+
+    #[test]
+    fn sudo_config() {
+        let mut app = mock_rujira_app();
+        let owner = app.api().addr_make("owner");
+        let borrower = app.api().addr_make("borrower");
+        let fees = app.api().addr_make("fees");
+        app.init_modules(|router, _, storage| {
+            router
+                .bank
+                .init_balance(storage, &owner, coins(1_000_000, "btc"))
+                .unwrap();
+        });
+        let contract = setup(&mut app, Decimal::percent(10), &fees);
+
+        app.execute_contract(
+            owner.clone(),
+            contract.clone(),
+            &ExecuteMsg::Deposit {
+                callback: None,
+                min_shares: None,
+                deadline: None,
+            },
+            &coins(1_000u128, "btc"),
+        )
+        .unwrap();
+        app.wasm_sudo(
+            contract.clone(),
+            &SudoMsg::SetBorrower {
+                contract: borrower.to_string(),
+                limit: Uint128::from(1_000u128),
+            },
+        )
+        .unwrap();
+        app.execute_contract(
+            borrower.clone(),
+            contract.clone(),
+            &ExecuteMsg::Market(MarketMsg::Borrow {
+                callback: None,
+                amount: Uint128::from(500u128),
+                delegate: None,
+            }),
+            &[],
+        )
+        .unwrap();
+
+        app.update_block(|x| x.time = x.time.plus_seconds(31_536_000));
+
+        // 500 * 16.25% = 81.25 interest, of which 8 is owed to the current fee address
+        let res = app
+            .wasm_sudo(contract.clone(), &SudoMsg::SetFee(Decimal::percent(20)))
+            .unwrap();
+        res.assert_event(&Event::new("mint").add_attributes(vec![
+            ("amount", "8"),
+            ("denom", "x/ghost-vault/btc"),
+            ("recipient", fees.as_str()),
+        ]));
+        res.assert_event(
+            &Event::new("wasm-rujira-ghost-vault/config").add_attributes(vec![("fee", "0.2")]),
+        );
+
+        app.wasm_sudo(contract.clone(), &SudoMsg::SetFee(Decimal::one()))
+            .unwrap_err();
+
+        let res = app
+            .wasm_sudo(contract.clone(), &SudoMsg::SetFeeAddress(owner.to_string()))
+            .unwrap();
+        res.assert_event(
+            &Event::new("wasm-rujira-ghost-vault/config")
+                .add_attributes(vec![("fee_address", owner.as_str())]),
+        );
+
+        let config: SettingsResponse = app
+            .wrap()
+            .query_wasm_smart(contract.clone(), &QueryMsg::Settings {})
+            .unwrap();
+        assert_eq!(config.fee, Decimal::percent(20));
+        assert_eq!(config.fee_address, owner.to_string());
+
+        let res = app
+            .wasm_sudo(
+                contract.clone(),
+                &SudoMsg::SetReceiptMetadata(TokenMetadata {
+                    description: "Ghost Vault BTC".to_string(),
+                    display: "gBTC".to_string(),
+                    name: "Ghost Vault BTC".to_string(),
+                    symbol: "gBTC".to_string(),
+                    uri: None,
+                    uri_hash: None,
+                }),
+            )
+            .unwrap();
+        assert!(res
+            .events
+            .iter()
+            .any(|e| e.ty == "wasm-rujira-ghost-vault/config"
+                && e.attributes.iter().any(|a| a.key == "receipt")));
+
+        app.wasm_sudo(
+            contract.clone(),
+            &SudoMsg::SetInterest(InterestModel::Fixed(Fixed {
+                rate: Decimal::percent(5),
+            })),
+        )
+        .unwrap();
+        let status: StatusResponse = app
+            .wrap()
+            .query_wasm_smart(contract.clone(), &QueryMsg::Status {})
+            .unwrap();
+        assert_eq!(status.debt_rate, Decimal::percent(5));
+
+        app.wasm_sudo(
+            contract.clone(),
+            &SudoMsg::SetInterest(InterestModel::Piecewise(Piecewise { points: vec![] })),
+        )
+        .unwrap_err();
+    }
+
+    #[test]
+    fn pause() {
+        let mut app = mock_rujira_app();
+        let owner = app.api().addr_make("owner");
+        let borrower = app.api().addr_make("borrower");
+        let guardian = app.api().addr_make("guardian");
+        app.init_modules(|router, _, storage| {
+            router
+                .bank
+                .init_balance(storage, &owner, coins(1_000_000, "btc"))
+                .unwrap();
+        });
+        let contract = setup(&mut app, Decimal::zero(), &owner);
+
+        app.execute_contract(
+            owner.clone(),
+            contract.clone(),
+            &ExecuteMsg::Deposit {
+                callback: None,
+                min_shares: None,
+                deadline: None,
+            },
+            &coins(1_000u128, "btc"),
+        )
+        .unwrap();
+        app.wasm_sudo(
+            contract.clone(),
+            &SudoMsg::SetBorrower {
+                contract: borrower.to_string(),
+                limit: Uint128::from(500u128),
+            },
+        )
+        .unwrap();
+        app.wasm_sudo(
+            contract.clone(),
+            &SudoMsg::SetGuardian(Some(guardian.to_string())),
+        )
+        .unwrap();
+        app.execute_contract(
+            borrower.clone(),
+            contract.clone(),
+            &ExecuteMsg::Market(MarketMsg::Borrow {
+                callback: None,
+                amount: Uint128::from(100u128),
+                delegate: None,
+            }),
+            &[],
+        )
+        .unwrap();
+
+        // Only the guardian can pause
+        app.execute_contract(
+            owner.clone(),
+            contract.clone(),
+            &ExecuteMsg::Guardian(GuardianMsg::Shutdown {}),
+            &[],
+        )
+        .unwrap_err();
+
+        let res = app
+            .execute_contract(
+                guardian.clone(),
+                contract.clone(),
+                &ExecuteMsg::Guardian(GuardianMsg::Shutdown {}),
+                &[],
+            )
+            .unwrap();
+        res.assert_event(
+            &Event::new("wasm-rujira-ghost-vault/config").add_attributes(vec![(
+                "pause",
+                r#"{"deposit":true,"withdraw":false,"borrow":true,"repay":false,"flash_loan":true}"#,
+            )]),
+        );
+
+        // New exposure is blocked
+        app.execute_contract(
+            owner.clone(),
+            contract.clone(),
+            &ExecuteMsg::Deposit {
+                callback: None,
+                min_shares: None,
+                deadline: None,
+            },
+            &coins(1_000u128, "btc"),
+        )
+        .unwrap_err();
+        app.execute_contract(
+            borrower.clone(),
+            contract.clone(),
+            &ExecuteMsg::Market(MarketMsg::Borrow {
+                callback: None,
+                amount: Uint128::from(100u128),
+                delegate: None,
+            }),
+            &[],
+        )
+        .unwrap_err();
+
+        // Exits stay open
+        app.execute_contract(
+            owner.clone(),
+            contract.clone(),
+            &ExecuteMsg::Withdraw {
+                callback: None,
+                amount: None,
+                min_amount: None,
+                deadline: None,
+            },
+            &coins(100u128, "x/ghost-vault/btc"),
+        )
+        .unwrap();
+        app.execute_contract(
+            borrower.clone(),
+            contract.clone(),
+            &ExecuteMsg::Market(MarketMsg::Repay { delegate: None }),
+            &coins(50u128, "btc"),
+        )
+        .unwrap();
+
+        // The guardian can only add restrictions
+        app.execute_contract(
+            guardian.clone(),
+            contract.clone(),
+            &ExecuteMsg::Guardian(GuardianMsg::Pause(Pause {
+                withdraw: true,
+                ..Pause::default()
+            })),
+            &[],
+        )
+        .unwrap();
+        let config: SettingsResponse = app
+            .wrap()
+            .query_wasm_smart(contract.clone(), &QueryMsg::Settings {})
+            .unwrap();
+        assert_eq!(
+            config.pause,
+            Pause {
+                deposit: true,
+                withdraw: true,
+                borrow: true,
+                repay: false,
+                flash_loan: true,
+            }
+        );
+        app.execute_contract(
+            owner.clone(),
+            contract.clone(),
+            &ExecuteMsg::Withdraw {
+                callback: None,
+                amount: None,
+                min_amount: None,
+                deadline: None,
+            },
+            &coins(100u128, "x/ghost-vault/btc"),
+        )
+        .unwrap_err();
+
+        // Governance lifts the pause
+        app.wasm_sudo(contract.clone(), &SudoMsg::SetPause(Pause::default()))
+            .unwrap();
+        app.execute_contract(
+            owner.clone(),
+            contract.clone(),
+            &ExecuteMsg::Deposit {
+                callback: None,
+                min_shares: None,
+                deadline: None,
+            },
+            &coins(1_000u128, "btc"),
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn deposit_cap() {
+        let mut app = mock_rujira_app();
+        let owner = app.api().addr_make("owner");
+        let other = app.api().addr_make("other");
+        app.init_modules(|router, _, storage| {
+            router
+                .bank
+                .init_balance(storage, &owner, coins(1_000_000, "btc"))
+                .unwrap();
+            router
+                .bank
+                .init_balance(storage, &other, coins(1_000_000, "btc"))
+                .unwrap();
+        });
+        let contract = setup(&mut app, Decimal::zero(), &owner);
+
+        app.wasm_sudo(
+            contract.clone(),
+            &SudoMsg::SetDepositCap {
+                total: Some(Uint128::from(1_500u128)),
+                depositor: Some(Uint128::from(1_000u128)),
+            },
+        )
+        .unwrap();
+        let config: SettingsResponse = app
+            .wrap()
+            .query_wasm_smart(contract.clone(), &QueryMsg::Settings {})
+            .unwrap();
+        assert_eq!(config.deposit_cap, Some(Uint128::from(1_500u128)));
+        assert_eq!(config.depositor_cap, Some(Uint128::from(1_000u128)));
+
+        app.execute_contract(
+            owner.clone(),
+            contract.clone(),
+            &ExecuteMsg::Deposit {
+                callback: None,
+                min_shares: None,
+                deadline: None,
+            },
+            &coins(1_000u128, "btc"),
+        )
+        .unwrap();
+
+        // The owner's receipt tokens already hold the per-address cap
+        app.execute_contract(
+            owner.clone(),
+            contract.clone(),
+            &ExecuteMsg::Deposit {
+                callback: None,
+                min_shares: None,
+                deadline: None,
+            },
+            &coins(1u128, "btc"),
+        )
+        .unwrap_err();
+
+        app.execute_contract(
+            other.clone(),
+            contract.clone(),
+            &ExecuteMsg::Deposit {
+                callback: None,
+                min_shares: None,
+                deadline: None,
+            },
+            &coins(500u128, "btc"),
+        )
+        .unwrap();
+
+        // The vault is full
+        app.execute_contract(
+            other.clone(),
+            contract.clone(),
+            &ExecuteMsg::Deposit {
+                callback: None,
+                min_shares: None,
+                deadline: None,
+            },
+            &coins(1u128, "btc"),
+        )
+        .unwrap_err();
+
+        // Withdrawing frees up room under both caps
+        app.execute_contract(
+            owner.clone(),
+            contract.clone(),
+            &ExecuteMsg::Withdraw {
+                callback: None,
+                amount: None,
+                min_amount: None,
+                deadline: None,
+            },
+            &coins(100u128, "x/ghost-vault/btc"),
+        )
+        .unwrap();
+        app.execute_contract(
+            owner.clone(),
+            contract.clone(),
+            &ExecuteMsg::Deposit {
+                callback: None,
+                min_shares: None,
+                deadline: None,
+            },
+            &coins(100u128, "btc"),
+        )
+        .unwrap();
+
+        app.wasm_sudo(
+            contract.clone(),
+            &SudoMsg::SetDepositCap {
+                total: None,
+                depositor: None,
+            },
+        )
+        .unwrap();
+        app.execute_contract(
+            owner.clone(),
+            contract.clone(),
+            &ExecuteMsg::Deposit {
+                callback: None,
+                min_shares: None,
+                deadline: None,
+            },
+            &coins(1_000u128, "btc"),
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn withdrawal_queue() {
+        let mut app = mock_rujira_app();
+        let owner = app.api().addr_make("owner");
+        let other = app.api().addr_make("other");
+        let borrower = app.api().addr_make("borrower");
+        app.init_modules(|router, _, storage| {
+            router
+                .bank
+                .init_balance(storage, &owner, coins(1_000_000, "btc"))
+                .unwrap();
+            router
+                .bank
+                .init_balance(storage, &other, coins(1_000_000, "btc"))
+                .unwrap();
+        });
+        let contract = setup(&mut app, Decimal::zero(), &owner);
+
+        app.execute_contract(
+            owner.clone(),
+            contract.clone(),
+            &ExecuteMsg::Deposit {
+                callback: None,
+                min_shares: None,
+                deadline: None,
+            },
+            &coins(1_000u128, "btc"),
+        )
+        .unwrap();
+        app.execute_contract(
+            other.clone(),
+            contract.clone(),
+            &ExecuteMsg::Deposit {
+                callback: None,
+                min_shares: None,
+                deadline: None,
+            },
+            &coins(100u128, "btc"),
+        )
+        .unwrap();
+        app.wasm_sudo(
+            contract.clone(),
+            &SudoMsg::SetBorrower {
+                contract: borrower.to_string(),
+                limit: Uint128::from(1_000u128),
+            },
+        )
+        .unwrap();
+        app.execute_contract(
+            borrower.clone(),
+            contract.clone(),
+            &ExecuteMsg::Market(MarketMsg::Borrow {
+                callback: None,
+                amount: Uint128::from(1_000u128),
+                delegate: None,
+            }),
+            &[],
+        )
+        .unwrap();
+
+        // Only 100 is left in the vault
+        app.execute_contract(
+            owner.clone(),
+            contract.clone(),
+            &ExecuteMsg::Withdraw {
+                callback: None,
+                amount: None,
+                min_amount: None,
+                deadline: None,
+            },
+            &coins(300u128, "x/ghost-vault/btc"),
+        )
+        .unwrap_err();
+
+        let res = app
+            .execute_contract(
+                owner.clone(),
+                contract.clone(),
+                &ExecuteMsg::QueueWithdraw {},
+                &coins(300u128, "x/ghost-vault/btc"),
+            )
+            .unwrap();
+        res.assert_event(
+            &Event::new("wasm-rujira-ghost-vault/withdraw_queue").add_attributes(vec![
+                ("owner", owner.as_str()),
+                ("id", "0"),
+                ("shares", "300"),
+            ]),
+        );
+
+        // The second ticket could be paid, but waits behind the first
+        app.execute_contract(
+            other.clone(),
+            contract.clone(),
+            &ExecuteMsg::QueueWithdraw {},
+            &coins(50u128, "x/ghost-vault/btc"),
+        )
+        .unwrap();
+
+        let queue: WithdrawalQueueResponse = app
+            .wrap()
+            .query_wasm_smart(
+                contract.clone(),
+                &QueryMsg::WithdrawalQueue {
+                    limit: None,
+                    start_after: None,
+                },
+            )
+            .unwrap();
+        assert_eq!(queue.shares, Uint128::from(350u128));
+        assert_eq!(queue.value, Uint128::from(350u128));
+        assert_eq!(
+            queue.tickets.iter().map(|t| t.id).collect::<Vec<u64>>(),
+            vec![0, 1]
+        );
+
+        // Queued withdrawals take priority over new borrows and withdrawals
+        app.wasm_sudo(
+            contract.clone(),
+            &SudoMsg::SetBorrower {
+                contract: borrower.to_string(),
+                limit: Uint128::from(2_000u128),
+            },
+        )
+        .unwrap();
+        app.execute_contract(
+            borrower.clone(),
+            contract.clone(),
+            &ExecuteMsg::Market(MarketMsg::Borrow {
+                callback: None,
+                amount: Uint128::from(1u128),
+                delegate: None,
+            }),
+            &[],
+        )
+        .unwrap_err();
+        app.execute_contract(
+            other.clone(),
+            contract.clone(),
+            &ExecuteMsg::Withdraw {
+                callback: None,
+                amount: None,
+                min_amount: None,
+                deadline: None,
+            },
+            &coins(10u128, "x/ghost-vault/btc"),
+        )
+        .unwrap_err();
+
+        // Tickets can only be claimed once filled, and cancelled by their owner
+        app.execute_contract(
+            owner.clone(),
+            contract.clone(),
+            &ExecuteMsg::ClaimWithdraw { id: 0 },
+            &[],
+        )
+        .unwrap_err();
+        app.execute_contract(
+            owner.clone(),
+            contract.clone(),
+            &ExecuteMsg::CancelWithdraw { id: 1 },
+            &[],
+        )
+        .unwrap_err();
+        let res = app
+            .execute_contract(
+                other.clone(),
+                contract.clone(),
+                &ExecuteMsg::CancelWithdraw { id: 1 },
+                &[],
+            )
+            .unwrap();
+        res.assert_event(&Event::new("transfer").add_attributes(vec![
+            ("amount", "50x/ghost-vault/btc"),
+            ("recipient", other.as_str()),
+        ]));
+
+        // A repay fills the head of the queue
+        let res = app
+            .execute_contract(
+                borrower.clone(),
+                contract.clone(),
+                &ExecuteMsg::Market(MarketMsg::Repay { delegate: None }),
+                &coins(250u128, "btc"),
+            )
+            .unwrap();
+        res.assert_event(
+            &Event::new("wasm-rujira-ghost-vault/withdraw_fill").add_attributes(vec![
+                ("owner", owner.as_str()),
+                ("id", "0"),
+                ("amount", "300"),
+                ("shares", "300"),
+            ]),
+        );
+        res.assert_event(
+            &Event::new("burn")
+                .add_attributes(vec![("amount", "300"), ("denom", "x/ghost-vault/btc")]),
+        );
+
+        let ticket: TicketResponse = app
+            .wrap()
+            .query_wasm_smart(contract.clone(), &QueryMsg::WithdrawalTicket { id: 0 })
+            .unwrap();
+        assert!(ticket.filled);
+        assert_eq!(ticket.amount, Uint128::from(300u128));
+
+        let queue: WithdrawalQueueResponse = app
+            .wrap()
+            .query_wasm_smart(
+                contract.clone(),
+                &QueryMsg::WithdrawalQueue {
+                    limit: None,
+                    start_after: None,
+                },
+            )
+            .unwrap();
+        assert_eq!(queue.shares, Uint128::zero());
+        assert!(queue.tickets.is_empty());
+
+        // The filled amount is set aside from the borrowable liquidity
+        let b: BorrowerResponse = app
+            .wrap()
+            .query_wasm_smart(
+                contract.clone(),
+                &QueryMsg::Borrower {
+                    addr: borrower.to_string(),
+                },
+            )
+            .unwrap();
+        assert_eq!(b.available, Uint128::from(50u128));
+
+        app.execute_contract(
+            other.clone(),
+            contract.clone(),
+            &ExecuteMsg::ClaimWithdraw { id: 0 },
+            &[],
+        )
+        .unwrap_err();
+        let res = app
+            .execute_contract(
+                owner.clone(),
+                contract.clone(),
+                &ExecuteMsg::ClaimWithdraw { id: 0 },
+                &[],
+            )
+            .unwrap();
+        res.assert_event(
+            &Event::new("transfer")
+                .add_attributes(vec![("amount", "300btc"), ("recipient", owner.as_str())]),
+        );
+        app.wrap()
+            .query_wasm_smart::<TicketResponse>(
+                contract.clone(),
+                &QueryMsg::WithdrawalTicket { id: 0 },
+            )
+            .unwrap_err();
+    }
+
+    #[test]
+    fn write_off() {
+        let mut app = mock_rujira_app();
+        let owner = app.api().addr_make("owner");
+        let borrower = app.api().addr_make("borrower");
+        let delegate = app.api().addr_make("delegate");
+        app.init_modules(|router, _, storage| {
+            router
+                .bank
+                .init_balance(storage, &owner, coins(1_000_000, "btc"))
+                .unwrap();
+        });
+        let contract = setup(&mut app, Decimal::zero(), &owner);
+
+        app.execute_contract(
+            owner.clone(),
+            contract.clone(),
+            &ExecuteMsg::Deposit {
+                callback: None,
+                min_shares: None,
+                deadline: None,
+            },
+            &coins(1_000u128, "btc"),
+        )
+        .unwrap();
+        app.wasm_sudo(
+            contract.clone(),
+            &SudoMsg::SetBorrower {
+                contract: borrower.to_string(),
+                limit: Uint128::from(1_000u128),
+            },
+        )
+        .unwrap();
+        for delegate in [None, Some(delegate.to_string())] {
+            app.execute_contract(
+                borrower.clone(),
+                contract.clone(),
+                &ExecuteMsg::Market(MarketMsg::Borrow {
+                    callback: None,
+                    amount: Uint128::from(250u128),
+                    delegate,
+                }),
+                &[],
+            )
+            .unwrap();
+        }
+
+        // Write off the delegate's obligation
+        let res = app
+            .wasm_sudo(
+                contract.clone(),
+                &SudoMsg::WriteOff {
+                    borrower: borrower.to_string(),
+                    delegate: Some(delegate.to_string()),
+                    shares: None,
+                },
+            )
+            .unwrap();
+        res.assert_event(
+            &Event::new("wasm-rujira-ghost-vault/write_off").add_attributes(vec![
+                ("borrower", borrower.as_str()),
+                ("delegate", delegate.as_str()),
+                ("amount", "250"),
+                ("shares", "250"),
+            ]),
+        );
+        let d: DelegateResponse = app
+            .wrap()
+            .query_wasm_smart(
+                contract.clone(),
+                &QueryMsg::Delegate {
+                    borrower: borrower.to_string(),
+                    addr: delegate.to_string(),
+                },
+            )
+            .unwrap();
+        assert_eq!(d.shares, Uint128::zero());
+        assert_eq!(d.borrower.shares, Uint128::from(250u128));
+
+        // And then the rest of the borrower's debt
+        app.wasm_sudo(
+            contract.clone(),
+            &SudoMsg::WriteOff {
+                borrower: borrower.to_string(),
+                delegate: None,
+                shares: Some(Uint128::from(100u128)),
+            },
+        )
+        .unwrap();
+        app.wasm_sudo(
+            contract.clone(),
+            &SudoMsg::WriteOff {
+                borrower: borrower.to_string(),
+                delegate: None,
+                shares: None,
+            },
+        )
+        .unwrap();
+
+        let status: StatusResponse = app
+            .wrap()
+            .query_wasm_smart(contract.clone(), &QueryMsg::Status {})
+            .unwrap();
+        assert_eq!(status.debt_pool.size, Uint128::zero());
+        assert_eq!(status.deposit_pool.size, Uint128::from(500u128));
+        assert_eq!(status.deposit_pool.shares, Uint128::from(1_000u128));
+        assert_eq!(status.deposit_pool.ratio, Decimal::percent(50));
+
+        // Depositors realise the loss
+        let res = app
+            .execute_contract(
+                owner.clone(),
+                contract.clone(),
+                &ExecuteMsg::Withdraw {
+                    callback: None,
+                    amount: None,
+                    min_amount: None,
+                    deadline: None,
+                },
+                &coins(200u128, "x/ghost-vault/btc"),
+            )
+            .unwrap();
+        res.assert_event(
+            &Event::new("wasm-rujira-ghost-vault/withdraw").add_attributes(vec![
+                ("amount", "100"),
+                ("owner", owner.as_str()),
+                ("shares", "200"),
+            ]),
+        );
+    }
+
+    #[test]
+    fn write_off_partial() {
+        let mut app = mock_rujira_app();
+        let owner = app.api().addr_make("owner");
+        let borrower = app.api().addr_make("borrower");
+        let delegate = app.api().addr_make("delegate");
+        app.init_modules(|router, _, storage| {
+            router
+                .bank
+                .init_balance(storage, &owner, coins(1_000, "btc"))
+                .unwrap();
+        });
+        let contract = setup(&mut app, Decimal::zero(), &owner);
+
+        app.execute_contract(
+            owner.clone(),
+            contract.clone(),
+            &ExecuteMsg::Deposit {
+                callback: None,
+                min_shares: None,
+                deadline: None,
+            },
+            &coins(1_000u128, "btc"),
+        )
+        .unwrap();
+        app.wasm_sudo(
+            contract.clone(),
+            &SudoMsg::SetBorrower {
+                contract: borrower.to_string(),
+                limit: Uint128::from(1_000u128),
+            },
+        )
+        .unwrap();
+        for delegate in [None, Some(delegate.to_string())] {
+            app.execute_contract(
+                borrower.clone(),
+                contract.clone(),
+                &ExecuteMsg::Market(MarketMsg::Borrow {
+                    callback: None,
+                    amount: Uint128::from(250u128),
+                    delegate,
+                }),
+                &[],
+            )
+            .unwrap();
+        }
+
+        let delegates = |app: &RujiraApp| -> DelegatesResponse {
+            app.wrap()
+                .query_wasm_smart(
+                    contract.clone(),
+                    &QueryMsg::Delegates {
+                        borrower: borrower.to_string(),
+                        limit: None,
+                        start_after: None,
+                    },
+                )
+                .unwrap()
+        };
+
+        // Only the undelegated debt is reached without a delegate
+        let res = app
+            .wasm_sudo(
+                contract.clone(),
+                &SudoMsg::WriteOff {
+                    borrower: borrower.to_string(),
+                    delegate: None,
+                    shares: Some(Uint128::from(400u128)),
+                },
+            )
+            .unwrap();
+        res.assert_event(
+            &Event::new("wasm-rujira-ghost-vault/write_off")
+                .add_attributes(vec![("amount", "250"), ("shares", "250")]),
+        );
+        let res = delegates(&app);
+        assert_eq!(res.borrower.shares, Uint128::from(250u128));
+        assert_eq!(res.delegated_shares, Uint128::from(250u128));
+        assert_eq!(res.undelegated_shares, Uint128::zero());
+
+        app.wasm_sudo(
+            contract.clone(),
+            &SudoMsg::WriteOff {
+                borrower: borrower.to_string(),
+                delegate: Some(delegate.to_string()),
+                shares: Some(Uint128::from(100u128)),
+            },
+        )
+        .unwrap();
+        let res = delegates(&app);
+        assert_eq!(res.borrower.shares, Uint128::from(150u128));
+        assert_eq!(res.delegated_shares, Uint128::from(150u128));
+        assert!(res.delegated_shares <= res.borrower.shares);
+    }
+
+    #[test]
+    fn reserve() {
+        let mut app = mock_rujira_app();
+        let owner = app.api().addr_make("owner");
+        let borrower = app.api().addr_make("borrower");
+        let fees = app.api().addr_make("fees");
+        app.init_modules(|router, _, storage| {
+            router
+                .bank
+                .init_balance(storage, &owner, coins(1_000_000, "btc"))
+                .unwrap();
+            router
+                .bank
+                .init_balance(storage, &fees, coins(100, "btc"))
+                .unwrap();
+        });
+        let contract = setup(&mut app, Decimal::percent(10), &fees);
+
+        app.wasm_sudo(
+            contract.clone(),
+            &SudoMsg::SetReserveFraction(Decimal::percent(50)),
+        )
+        .unwrap();
+        app.wasm_sudo(
+            contract.clone(),
+            &SudoMsg::SetReserveFraction(Decimal::percent(101)),
+        )
+        .unwrap_err();
+        app.execute_contract(
+            owner.clone(),
+            contract.clone(),
+            &ExecuteMsg::Deposit {
+                callback: None,
+                min_shares: None,
+                deadline: None,
+            },
+            &coins(1_000u128, "btc"),
+        )
+        .unwrap();
+        app.wasm_sudo(
+            contract.clone(),
+            &SudoMsg::SetBorrower {
+                contract: borrower.to_string(),
+                limit: Uint128::from(1_000u128),
+            },
+        )
+        .unwrap();
+        app.execute_contract(
+            borrower.clone(),
+            contract.clone(),
+            &ExecuteMsg::Market(MarketMsg::Borrow {
+                callback: None,
+                amount: Uint128::from(500u128),
+                delegate: None,
+            }),
+            &[],
+        )
+        .unwrap();
+
+        app.update_block(|x| x.time = x.time.plus_seconds(31_536_000));
+
+        // Only the fee address or guardian can fund the reserve
+        app.execute_contract(
+            owner.clone(),
+            contract.clone(),
+            &ExecuteMsg::FundReserve {},
+            &coins(100u128, "btc"),
+        )
+        .unwrap_err();
+
+        // Half of the 8 fee shares are retained, the rest minted to the fee address
+        let res = app
+            .execute_contract(
+                fees.clone(),
+                contract.clone(),
+                &ExecuteMsg::FundReserve {},
+                &coins(100u128, "btc"),
+            )
+            .unwrap();
+        res.assert_event(&Event::new("mint").add_attributes(vec![
+            ("amount", "4"),
+            ("denom", "x/ghost-vault/btc"),
+            ("recipient", fees.as_str()),
+        ]));
+        res.assert_event(
+            &Event::new("wasm-rujira-ghost-vault/fund_reserve").add_attributes(vec![
+                ("sender", fees.as_str()),
+                ("amount", "100"),
+                ("shares", "93"),
+            ]),
+        );
+
+        let reserve: ReserveResponse = app
+            .wrap()
+            .query_wasm_smart(contract.clone(), &QueryMsg::Reserve {})
+            .unwrap();
+        assert_eq!(reserve.fraction, Decimal::percent(50));
+        assert_eq!(reserve.shares, Uint128::from(97u128));
+        assert_eq!(reserve.value, Uint128::from(104u128));
+
+        let res = app
+            .wasm_sudo(
+                contract.clone(),
+                &SudoMsg::SweepReserve {
+                    shares: Some(Uint128::from(10u128)),
+                    to: owner.to_string(),
+                },
+            )
+            .unwrap();
+        res.assert_event(&Event::new("mint").add_attributes(vec![
+            ("amount", "10"),
+            ("denom", "x/ghost-vault/btc"),
+            ("recipient", owner.as_str()),
+        ]));
+
+        // The reserve takes the first loss
+        let res = app
+            .wasm_sudo(
+                contract.clone(),
+                &SudoMsg::WriteOff {
+                    borrower: borrower.to_string(),
+                    delegate: None,
+                    shares: None,
+                },
+            )
+            .unwrap();
+        res.assert_event(
+            &Event::new("wasm-rujira-ghost-vault/write_off").add_attributes(vec![
+                ("amount", "581"),
+                ("shares", "500"),
+                ("reserve", "93"),
+            ]),
+        );
+
+        let reserve: ReserveResponse = app
+            .wrap()
+            .query_wasm_smart(contract.clone(), &QueryMsg::Reserve {})
+            .unwrap();
+        assert_eq!(reserve.shares, Uint128::zero());
+
+        let status: StatusResponse = app
+            .wrap()
+            .query_wasm_smart(contract.clone(), &QueryMsg::Status {})
+            .unwrap();
+        assert_eq!(status.deposit_pool.size, Uint128::from(600u128));
+        assert_eq!(status.deposit_pool.shares, Uint128::from(1_014u128));
+    }
+
+    #[test]
+    fn adaptive_interest() {
+        let mut app = mock_rujira_app();
+        let owner = app.api().addr_make("owner");
+        let borrower = app.api().addr_make("borrower");
+        app.init_modules(|router, _, storage| {
+            router
+                .bank
+                .init_balance(storage, &owner, coins(1_000_000, "btc"))
+                .unwrap();
+        });
+        let contract = setup(&mut app, Decimal::zero(), &owner);
+
+        let model = Adaptive {
+            target_utilization: Decimal::percent(80),
+            initial_rate: Decimal::percent(4),
+            min_rate: Decimal::percent(1),
+            max_rate: Decimal::percent(100),
+            steepness: Decimal::percent(400),
+            max_speed: Decimal::percent(5000),
+        };
+        app.wasm_sudo(
+            contract.clone(),
+            &SudoMsg::SetInterest(InterestModel::Adaptive(model.clone())),
+        )
+        .unwrap();
+        app.execute_contract(
+            owner.clone(),
+            contract.clone(),
+            &ExecuteMsg::Deposit {
+                callback: None,
+                min_shares: None,
+                deadline: None,
+            },
+            &coins(1_000u128, "btc"),
+        )
+        .unwrap();
+        app.wasm_sudo(
+            contract.clone(),
+            &SudoMsg::SetBorrower {
+                contract: borrower.to_string(),
+                limit: Uint128::from(1_000u128),
+            },
+        )
+        .unwrap();
+        app.execute_contract(
+            borrower.clone(),
+            contract.clone(),
+            &ExecuteMsg::Market(MarketMsg::Borrow {
+                callback: None,
+                amount: Uint128::from(900u128),
+                delegate: None,
+            }),
+            &[],
+        )
+        .unwrap();
+
+        let status: StatusResponse = app
+            .wrap()
+            .query_wasm_smart(contract.clone(), &QueryMsg::Status {})
+            .unwrap();
+        assert_eq!(status.debt_rate, Decimal::percent(10));
+        let interest: InterestResponse = app
+            .wrap()
+            .query_wasm_smart(contract.clone(), &QueryMsg::Interest {})
+            .unwrap();
+        assert_eq!(interest.rate_at_target, Some(Decimal::percent(4)));
+
+        // A day above target moves the curve up
+        app.update_block(|x| x.time = x.time.plus_days(1));
+        let status: StatusResponse = app
+            .wrap()
+            .query_wasm_smart(contract.clone(), &QueryMsg::Status {})
+            .unwrap();
+        let rate_at_target = Decimal::percent(4) + Decimal::from_ratio(1u128, 365u128);
+        assert!(status.debt_rate > Decimal::percent(10));
+        let interest: InterestResponse = app
+            .wrap()
+            .query_wasm_smart(contract.clone(), &QueryMsg::Interest {})
+            .unwrap();
+        assert_eq!(interest.rate_at_target, Some(rate_at_target));
+
+        // Re-tuning the model keeps the rate it has learned
+        app.wasm_sudo(
+            contract.clone(),
+            &SudoMsg::SetInterest(InterestModel::Adaptive(Adaptive {
+                max_speed: Decimal::percent(2500),
+                ..model.clone()
+            })),
+        )
+        .unwrap();
+        let interest: InterestResponse = app
+            .wrap()
+            .query_wasm_smart(contract.clone(), &QueryMsg::Interest {})
+            .unwrap();
+        assert_eq!(interest.rate_at_target, Some(rate_at_target));
+
+        // A full repay leaves the vault idle, and the curve drifts back down to its floor
+        app.execute_contract(
+            borrower.clone(),
+            contract.clone(),
+            &ExecuteMsg::Market(MarketMsg::Repay { delegate: None }),
+            &coins(900u128, "btc"),
+        )
+        .unwrap();
+        app.update_block(|x| x.time = x.time.plus_days(365));
+        let interest: InterestResponse = app
+            .wrap()
+            .query_wasm_smart(contract.clone(), &QueryMsg::Interest {})
+            .unwrap();
+        assert_eq!(interest.rate_at_target, Some(Decimal::percent(1)));
+
+        // Within the new bounds
+        app.wasm_sudo(
+            contract.clone(),
+            &SudoMsg::SetInterest(InterestModel::Adaptive(Adaptive {
+                min_rate: Decimal::percent(2),
+                ..model
+            })),
+        )
+        .unwrap();
+        let interest: InterestResponse = app
+            .wrap()
+            .query_wasm_smart(contract.clone(), &QueryMsg::Interest {})
+            .unwrap();
+        assert_eq!(interest.rate_at_target, Some(Decimal::percent(2)));
+    }
+
+    #[test]
+    fn ramp_interest() {
+        let mut app = mock_rujira_app();
+        let owner = app.api().addr_make("owner");
+        let contract = setup(&mut app, Decimal::zero(), &owner);
+
+        let to = InterestModel::Fixed(Fixed {
+            rate: Decimal::percent(50),
+        });
+        app.wasm_sudo(
+            contract.clone(),
+            &SudoMsg::RampInterest {
+                interest: to.clone(),
+                duration: 100 * 86_400,
+            },
+        )
+        .unwrap();
+
+        // Halfway between the 10% base rate and the new fixed 50%
+        app.update_block(|x| x.time = x.time.plus_days(50));
+        let status: StatusResponse = app
+            .wrap()
+            .query_wasm_smart(contract.clone(), &QueryMsg::Status {})
+            .unwrap();
+        assert_eq!(status.debt_rate, Decimal::percent(30));
+        let interest: InterestResponse = app
+            .wrap()
+            .query_wasm_smart(contract.clone(), &QueryMsg::Interest {})
+            .unwrap();
+        let ramp = interest.ramp.unwrap();
+        assert_eq!(ramp.progress, Decimal::percent(50));
+        assert_eq!(ramp.from_rate, Decimal::percent(10));
+        assert_eq!(ramp.to_rate, Decimal::percent(50));
+        assert_eq!(ramp.to, to);
+
+        // One ramp at a time
+        app.wasm_sudo(
+            contract.clone(),
+            &SudoMsg::RampInterest {
+                interest: to.clone(),
+                duration: 86_400,
+            },
+        )
+        .unwrap_err();
+
+        app.update_block(|x| x.time = x.time.plus_days(60));
+        let status: StatusResponse = app
+            .wrap()
+            .query_wasm_smart(contract.clone(), &QueryMsg::Status {})
+            .unwrap();
+        assert_eq!(status.debt_rate, Decimal::percent(50));
+        let interest: InterestResponse = app
+            .wrap()
+            .query_wasm_smart(contract.clone(), &QueryMsg::Interest {})
+            .unwrap();
+        assert_eq!(interest.ramp, None);
+
+        // The finished ramp is cleared once interest is next accrued
+        let ramp = |app: &RujiraApp| {
+            let raw = app
+                .wrap()
+                .query_wasm_raw(contract.clone(), b"config".to_vec())
+                .unwrap()
+                .unwrap();
+            cosmwasm_std::from_json::<Config>(raw).unwrap().ramp
+        };
+        assert!(ramp(&app).is_some());
+        app.execute_contract(owner.clone(), contract.clone(), &ExecuteMsg::Accrue {}, &[])
+            .unwrap();
+        assert_eq!(ramp(&app), None);
+
+        // An instant change cancels the ramp
+        app.wasm_sudo(contract.clone(), &SudoMsg::SetInterest(to))
+            .unwrap();
+        let interest: InterestResponse = app
+            .wrap()
+            .query_wasm_smart(contract.clone(), &QueryMsg::Interest {})
+            .unwrap();
+        assert_eq!(interest.ramp, None);
+    }
+
+    #[test]
+    fn accrue() {
+        let mut app = mock_rujira_app();
+        let owner = app.api().addr_make("owner");
+        let borrower = app.api().addr_make("borrower");
+        let keeper = app.api().addr_make("keeper");
+        let fees = app.api().addr_make("fees");
+        app.init_modules(|router, _, storage| {
+            router
+                .bank
+                .init_balance(storage, &owner, coins(1_000, "btc"))
+                .unwrap();
+        });
+        let contract = setup(&mut app, Decimal::percent(10), &fees);
+
+        app.execute_contract(
+            owner.clone(),
+            contract.clone(),
+            &ExecuteMsg::Deposit {
+                callback: None,
+                min_shares: None,
+                deadline: None,
+            },
+            &coins(1_000u128, "btc"),
+        )
+        .unwrap();
+        app.wasm_sudo(
+            contract.clone(),
+            &SudoMsg::SetBorrower {
+                contract: borrower.to_string(),
+                limit: Uint128::from(1_000u128),
+            },
+        )
+        .unwrap();
+        app.execute_contract(
+            borrower.clone(),
+            contract.clone(),
+            &ExecuteMsg::Market(MarketMsg::Borrow {
+                callback: None,
+                amount: Uint128::from(800u128),
+                delegate: None,
+            }),
+            &[],
+        )
+        .unwrap();
+
+        // Nothing has accrued within the same block
+        let res = app
+            .execute_contract(
+                keeper.clone(),
+                contract.clone(),
+                &ExecuteMsg::Accrue {},
+                &[],
+            )
+            .unwrap();
+        assert!(!res
+            .events
+            .iter()
+            .any(|e| e.ty == "wasm-rujira-ghost-vault/accrue"));
+
+        // A year at target utilization charges 20% on the 800 borrowed
+        app.update_block(|x| x.time = x.time.plus_days(365));
+        let res = app
+            .execute_contract(
+                keeper.clone(),
+                contract.clone(),
+                &ExecuteMsg::Accrue {},
+                &[],
+            )
+            .unwrap();
+        res.assert_event(
+            &Event::new("wasm-rujira-ghost-vault/accrue").add_attributes(vec![
+                ("seconds", "31536000"),
+                ("debt_rate", "0.2"),
+                ("interest", "144"),
+                ("fee", "16"),
+            ]),
+        );
+        let balance = app
+            .wrap()
+            .query_balance(&fees, "x/ghost-vault/btc")
+            .unwrap();
+        assert!(!balance.amount.is_zero());
+
+        // The accrual is persisted, so the status matches the stored pools
+        let status: StatusResponse = app
+            .wrap()
+            .query_wasm_smart(contract.clone(), &QueryMsg::Status {})
+            .unwrap();
+        assert_eq!(status.debt_pool.size, Uint128::from(960u128));
+        assert_eq!(status.deposit_pool.size, Uint128::from(1_160u128));
+    }
+
+    #[test]
+    fn slippage() {
+        let mut app = mock_rujira_app();
+        let owner = app.api().addr_make("owner");
+        app.init_modules(|router, _, storage| {
+            router
+                .bank
+                .init_balance(storage, &owner, coins(1_000, "btc"))
+                .unwrap();
+        });
+        let contract = setup(&mut app, Decimal::zero(), &owner);
+        let deadline = app.block_info().time;
+
+        app.execute_contract(
+            owner.clone(),
+            contract.clone(),
+            &ExecuteMsg::Deposit {
+                callback: None,
+                min_shares: Some(Uint128::from(1_001u128)),
+                deadline: None,
+            },
+            &coins(1_000u128, "btc"),
+        )
+        .unwrap_err();
+        app.execute_contract(
+            owner.clone(),
+            contract.clone(),
+            &ExecuteMsg::Deposit {
+                callback: None,
+                min_shares: Some(Uint128::from(1_000u128)),
+                deadline: Some(deadline),
+            },
+            &coins(1_000u128, "btc"),
+        )
+        .unwrap();
+
+        app.update_block(|x| x.time = x.time.plus_seconds(1));
+        app.execute_contract(
+            owner.clone(),
+            contract.clone(),
+            &ExecuteMsg::Withdraw {
+                callback: None,
+                amount: None,
+                min_amount: None,
+                deadline: Some(deadline),
+            },
+            &coins(500u128, "x/ghost-vault/btc"),
+        )
+        .unwrap_err();
+        app.execute_contract(
+            owner.clone(),
+            contract.clone(),
+            &ExecuteMsg::Withdraw {
+                callback: None,
+                amount: None,
+                min_amount: Some(Uint128::from(501u128)),
+                deadline: None,
+            },
+            &coins(500u128, "x/ghost-vault/btc"),
+        )
+        .unwrap_err();
+        app.execute_contract(
+            owner.clone(),
+            contract.clone(),
+            &ExecuteMsg::Withdraw {
+                callback: None,
+                amount: None,
+                min_amount: Some(Uint128::from(500u128)),
+                deadline: None,
+            },
+            &coins(500u128, "x/ghost-vault/btc"),
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn withdraw_exact() {
+        let mut app = mock_rujira_app();
+        let owner = app.api().addr_make("owner");
+        let borrower = app.api().addr_make("borrower");
+        app.init_modules(|router, _, storage| {
+            router
+                .bank
+                .init_balance(storage, &owner, coins(1_000, "btc"))
+                .unwrap();
+        });
+        let contract = setup(&mut app, Decimal::zero(), &owner);
+
+        app.execute_contract(
+            owner.clone(),
+            contract.clone(),
+            &ExecuteMsg::Deposit {
+                callback: None,
+                min_shares: None,
+                deadline: None,
+            },
+            &coins(1_000u128, "btc"),
+        )
+        .unwrap();
+        app.wasm_sudo(
+            contract.clone(),
+            &SudoMsg::SetBorrower {
+                contract: borrower.to_string(),
+                limit: Uint128::from(1_000u128),
+            },
+        )
+        .unwrap();
+        app.execute_contract(
+            borrower.clone(),
+            contract.clone(),
+            &ExecuteMsg::Market(MarketMsg::Borrow {
+                callback: None,
+                amount: Uint128::from(800u128),
+                delegate: None,
+            }),
+            &[],
+        )
+        .unwrap();
+        // 160 of interest takes each share to 1.16
+        app.update_block(|x| x.time = x.time.plus_days(365));
+
+        // 100 needs 86.2 shares, so 86 isn't enough
+        app.execute_contract(
+            owner.clone(),
+            contract.clone(),
+            &ExecuteMsg::Withdraw {
+                callback: None,
+                amount: Some(Uint128::from(100u128)),
+                min_amount: None,
+                deadline: None,
+            },
+            &coins(86u128, "x/ghost-vault/btc"),
+        )
+        .unwrap_err();
+
+        app.execute_contract(
+            owner.clone(),
+            contract.clone(),
+            &ExecuteMsg::Withdraw {
+                callback: None,
+                amount: Some(Uint128::from(100u128)),
+                min_amount: None,
+                deadline: None,
+            },
+            &coins(200u128, "x/ghost-vault/btc"),
+        )
+        .unwrap();
+
+        // 87 shares burnt and the other 113 refunded
+        let balance = app.wrap().query_balance(&owner, "btc").unwrap();
+        assert_eq!(balance.amount, Uint128::from(100u128));
+        let balance = app
+            .wrap()
+            .query_balance(&owner, "x/ghost-vault/btc")
+            .unwrap();
+        assert_eq!(balance.amount, Uint128::from(913u128));
+    }
+
+    #[test]
+    fn previews() {
+        let mut app = mock_rujira_app();
+        let owner = app.api().addr_make("owner");
+        let borrower = app.api().addr_make("borrower");
+        app.init_modules(|router, _, storage| {
+            router
+                .bank
+                .init_balance(storage, &owner, coins(1_000, "btc"))
+                .unwrap();
+        });
+        let contract = setup(&mut app, Decimal::zero(), &owner);
+
+        let preview: PreviewResponse = app
+            .wrap()
+            .query_wasm_smart(
+                contract.clone(),
+                &QueryMsg::PreviewDeposit {
+                    amount: Uint128::from(1_000u128),
+                },
+            )
+            .unwrap();
+        assert_eq!(preview.shares, Uint128::from(1_000u128));
+        assert_eq!(preview.utilization_ratio, Decimal::zero());
+
+        app.execute_contract(
+            owner.clone(),
+            contract.clone(),
+            &ExecuteMsg::Deposit {
+                callback: None,
+                min_shares: None,
+                deadline: None,
+            },
+            &coins(1_000u128, "btc"),
+        )
+        .unwrap();
+        app.wasm_sudo(
+            contract.clone(),
+            &SudoMsg::SetBorrower {
+                contract: borrower.to_string(),
+                limit: Uint128::from(600u128),
+            },
+        )
+        .unwrap();
+        app.execute_contract(
+            borrower.clone(),
+            contract.clone(),
+            &ExecuteMsg::Market(MarketMsg::Borrow {
+                callback: None,
+                amount: Uint128::from(400u128),
+                delegate: None,
+            }),
+            &[],
+        )
+        .unwrap();
+
+        // The borrower's limit binds before the vault's liquidity
+        let preview: PreviewResponse = app
+            .wrap()
+            .query_wasm_smart(
+                contract.clone(),
+                &QueryMsg::MaxBorrow {
+                    borrower: borrower.to_string(),
+                },
+            )
+            .unwrap();
+        assert_eq!(preview.amount, Uint128::from(200u128));
+        assert_eq!(preview.shares, Uint128::from(200u128));
+        assert_eq!(preview.utilization_ratio, Decimal::percent(60));
+        assert_eq!(preview.debt_rate, Decimal::permille(175));
+        app.wrap()
+            .query_wasm_smart::<PreviewResponse>(
+                contract.clone(),
+                &QueryMsg::PreviewBorrow {
+                    borrower: borrower.to_string(),
+                    amount: Uint128::from(300u128),
+                },
+            )
+            .unwrap_err();
+
+        // A full repay leaves the vault idle
+        let preview: PreviewResponse = app
+            .wrap()
+            .query_wasm_smart(
+                contract.clone(),
+                &QueryMsg::PreviewRepay {
+                    borrower: borrower.to_string(),
+                    amount: Uint128::from(400u128),
+                },
+            )
+            .unwrap();
+        assert_eq!(preview.shares, Uint128::from(400u128));
+        assert_eq!(preview.amount, Uint128::from(400u128));
+        assert_eq!(preview.utilization_ratio, Decimal::zero());
+
+        // Withdrawals are capped by the liquidity left after borrows
+        let preview: PreviewResponse = app
+            .wrap()
+            .query_wasm_smart(
+                contract.clone(),
+                &QueryMsg::MaxWithdraw {
+                    addr: owner.to_string(),
+                },
+            )
+            .unwrap();
+        assert_eq!(preview.shares, Uint128::from(600u128));
+        assert_eq!(preview.amount, Uint128::from(600u128));
+        assert_eq!(preview.utilization_ratio, Decimal::one());
+        app.wrap()
+            .query_wasm_smart::<PreviewResponse>(
+                contract.clone(),
+                &QueryMsg::PreviewWithdraw {
+                    shares: Uint128::from(700u128),
+                },
+            )
+            .unwrap_err();
+    }
+
+    #[test]
+    fn max_withdraw_written_off() {
+        let mut app = mock_rujira_app();
+        let owner = app.api().addr_make("owner");
+        let borrower = app.api().addr_make("borrower");
+        app.init_modules(|router, _, storage| {
+            router
+                .bank
+                .init_balance(storage, &owner, coins(1_000, "btc"))
+                .unwrap();
+        });
+        let contract = setup(&mut app, Decimal::zero(), &owner);
+
+        app.execute_contract(
+            owner.clone(),
+            contract.clone(),
+            &ExecuteMsg::Deposit {
+                callback: None,
+                min_shares: None,
+                deadline: None,
+            },
+            &coins(1_000u128, "btc"),
+        )
+        .unwrap();
+        app.wasm_sudo(
+            contract.clone(),
+            &SudoMsg::SetBorrower {
+                contract: borrower.to_string(),
+                limit: Uint128::from(1_000u128),
+            },
+        )
+        .unwrap();
+        app.execute_contract(
+            borrower.clone(),
+            contract.clone(),
+            &ExecuteMsg::Market(MarketMsg::Borrow {
+                callback: None,
+                amount: Uint128::from(1_000u128),
+                delegate: None,
+            }),
+            &[],
+        )
+        .unwrap();
+        app.wasm_sudo(
+            contract.clone(),
+            &SudoMsg::WriteOff {
+                borrower: borrower.to_string(),
+                delegate: None,
+                shares: None,
+            },
+        )
+        .unwrap();
+
+        // Receipt tokens remain against an empty deposit pool
+        let preview: PreviewResponse = app
+            .wrap()
+            .query_wasm_smart(
+                contract.clone(),
+                &QueryMsg::MaxWithdraw {
+                    addr: owner.to_string(),
+                },
+            )
+            .unwrap();
+        assert_eq!(preview.shares, Uint128::zero());
+        assert_eq!(preview.amount, Uint128::zero());
+    }
+
+    #[test]
+    fn positions() {
+        let mut app = mock_rujira_app();
+        let owner = app.api().addr_make("owner");
+        let other = app.api().addr_make("other");
+        app.init_modules(|router, _, storage| {
+            router
+                .bank
+                .init_balance(storage, &owner, coins(1_000, "btc"))
+                .unwrap();
+        });
+        let contract = setup(&mut app, Decimal::zero(), &owner);
+
+        app.execute_contract(
+            owner.clone(),
+            contract.clone(),
+            &ExecuteMsg::Deposit {
+                callback: None,
+                min_shares: None,
+                deadline: None,
+            },
+            &coins(1_000u128, "btc"),
+        )
+        .unwrap();
+        app.send_tokens(
+            owner.clone(),
+            other.clone(),
+            &coins(250u128, "x/ghost-vault/btc"),
+        )
+        .unwrap();
+
+        let position: PositionResponse = app
+            .wrap()
+            .query_wasm_smart(
+                contract.clone(),
+                &QueryMsg::Position {
+                    addr: other.to_string(),
+                },
+            )
+            .unwrap();
+        assert_eq!(position.shares, Uint128::from(250u128));
+        assert_eq!(position.value, Uint128::from(250u128));
+        assert_eq!(position.pool_share, Decimal::percent(25));
+
+        let res: PositionsResponse = app
+            .wrap()
+            .query_wasm_smart(
+                contract.clone(),
+                &QueryMsg::Positions {
+                    addrs: vec![owner.to_string(), other.to_string()],
+                },
+            )
+            .unwrap();
+        assert_eq!(res.positions.len(), 2);
+        assert_eq!(res.positions[0].shares, Uint128::from(750u128));
+        assert_eq!(res.positions[0].pool_share, Decimal::percent(75));
+        assert_eq!(res.positions[1], position);
+    }
+
+    #[test]
+    fn repay_for() {
+        let mut app = mock_rujira_app();
+        let owner = app.api().addr_make("owner");
+        let borrower = app.api().addr_make("borrower");
+        let user = app.api().addr_make("user");
+        let keeper = app.api().addr_make("keeper");
+        app.init_modules(|router, _, storage| {
+            router
+                .bank
+                .init_balance(storage, &owner, coins(1_000, "btc"))
+                .unwrap();
+            router
+                .bank
+                .init_balance(storage, &keeper, coins(1_000, "btc"))
+                .unwrap();
+        });
+        let contract = setup(&mut app, Decimal::zero(), &owner);
+
+        app.execute_contract(
+            owner.clone(),
+            contract.clone(),
+            &ExecuteMsg::Deposit {
+                callback: None,
+                min_shares: None,
+                deadline: None,
+            },
+            &coins(1_000u128, "btc"),
+        )
+        .unwrap();
+        app.wasm_sudo(
+            contract.clone(),
+            &SudoMsg::SetBorrower {
+                contract: borrower.to_string(),
+                limit: Uint128::from(1_000u128),
+            },
+        )
+        .unwrap();
+        app.execute_contract(
+            borrower.clone(),
+            contract.clone(),
+            &ExecuteMsg::Market(MarketMsg::Borrow {
+                callback: None,
+                amount: Uint128::from(400u128),
+                delegate: None,
+            }),
+            &[],
+        )
+        .unwrap();
+        app.execute_contract(
+            borrower.clone(),
+            contract.clone(),
+            &ExecuteMsg::Market(MarketMsg::Borrow {
+                callback: None,
+                amount: Uint128::from(400u128),
+                delegate: Some(user.to_string()),
+            }),
+            &[],
+        )
+        .unwrap();
+
+        // Only whitelisted borrowers can be repaid
+        app.execute_contract(
+            keeper.clone(),
+            contract.clone(),
+            &ExecuteMsg::RepayFor {
+                borrower: keeper.to_string(),
+                delegate: None,
+            },
+            &coins(100u128, "btc"),
+        )
+        .unwrap_err();
+
+        let res = app
+            .execute_contract(
+                keeper.clone(),
+                contract.clone(),
+                &ExecuteMsg::RepayFor {
+                    borrower: borrower.to_string(),
+                    delegate: Some(user.to_string()),
+                },
+                &coins(500u128, "btc"),
+            )
+            .unwrap();
+        res.assert_event(
+            &Event::new("wasm-rujira-ghost-vault/repay").add_attributes(vec![
+                ("borrower", borrower.as_str()),
+                ("payer", keeper.as_str()),
+                ("amount", "400"),
+            ]),
+        );
+
+        // The overpayment goes back to the keeper, not the borrower or delegate
+        let balance = app.wrap().query_balance(&keeper, "btc").unwrap();
+        assert_eq!(balance.amount, Uint128::from(600u128));
+        let res: DelegateResponse = app
+            .wrap()
+            .query_wasm_smart(
+                contract.clone(),
+                &QueryMsg::Delegate {
+                    borrower: borrower.to_string(),
+                    addr: user.to_string(),
+                },
+            )
+            .unwrap();
+        assert_eq!(res.current, Uint128::zero());
+        assert_eq!(res.borrower.current, Uint128::from(400u128));
+
+        // Repaying debt that is no longer owed refunds all of it, leaving the pool untouched
+        app.execute_contract(
+            keeper.clone(),
+            contract.clone(),
+            &ExecuteMsg::RepayFor {
+                borrower: borrower.to_string(),
+                delegate: Some(user.to_string()),
+            },
+            &coins(100u128, "btc"),
+        )
+        .unwrap();
+        let balance = app.wrap().query_balance(&keeper, "btc").unwrap();
+        assert_eq!(balance.amount, Uint128::from(600u128));
+        let res: StatusResponse = app
+            .wrap()
+            .query_wasm_smart(contract.clone(), &QueryMsg::Status {})
+            .unwrap();
+        assert_eq!(res.debt_pool.size, Uint128::from(400u128));
+        assert_eq!(res.debt_pool.shares, Uint128::from(400u128));
+    }
+
+    #[test]
+    fn flash_loan() {
+        let mut app = mock_rujira_app();
+        let owner = app.api().addr_make("owner");
+        let fees = app.api().addr_make("fees");
+        let receiver = FlashReceiver::create(&mut app, &owner);
+        app.init_modules(|router, _, storage| {
+            router
+                .bank
+                .init_balance(storage, &owner, coins(100_000, "btc"))
+                .unwrap();
+            router
+                .bank
+                .init_balance(storage, &receiver.0, coins(1_000, "btc"))
+                .unwrap();
+        });
+        let contract = setup(&mut app, Decimal::percent(20), &fees);
+        let vault = GhostVault(contract.clone());
+
+        app.execute_contract(
+            owner.clone(),
+            contract.clone(),
+            &ExecuteMsg::Deposit {
+                callback: None,
+                min_shares: None,
+                deadline: None,
+            },
+            &coins(100_000u128, "btc"),
+        )
+        .unwrap();
+        app.wasm_sudo(contract.clone(), &SudoMsg::SetFlashFee(Decimal::percent(1)))
+            .unwrap();
+        app.wasm_sudo(
+            contract.clone(),
+            &SudoMsg::SetFlashLimit(Some(Uint128::from(60_000u128))),
+        )
+        .unwrap();
+
+        // Over the limit
+        receiver
+            .flash_loan(
+                &mut app,
+                &owner,
+                &vault,
+                Uint128::from(70_000u128),
+                Uint128::from(700u128),
+            )
+            .unwrap_err();
+        // Short of the 500 fee
+        receiver
+            .flash_loan(
+                &mut app,
+                &owner,
+                &vault,
+                Uint128::from(50_000u128),
+                Uint128::from(499u128),
+            )
+            .unwrap_err();
+
+        let res = receiver
+            .flash_loan(
+                &mut app,
+                &owner,
+                &vault,
+                Uint128::from(50_000u128),
+                Uint128::from(500u128),
+            )
+            .unwrap();
+        res.assert_event(
+            &Event::new("wasm-rujira-ghost-vault/flash_loan").add_attributes(vec![
+                ("borrower", receiver.0.as_str()),
+                ("amount", "50000"),
+                ("fee", "500"),
+            ]),
+        );
+
+        // Depositors keep 400 of the fee, the protocol's 100 is minted as fee shares
+        let status: StatusResponse = app
+            .wrap()
+            .query_wasm_smart(contract.clone(), &QueryMsg::Status {})
+            .unwrap();
+        assert_eq!(status.deposit_pool.size, Uint128::from(100_500u128));
+        let balance = app
+            .wrap()
+            .query_balance(&fees, "x/ghost-vault/btc")
+            .unwrap();
+        assert_eq!(balance.amount, Uint128::from(99u128));
+
+        app.wasm_sudo(
+            contract.clone(),
+            &SudoMsg::SetPause(Pause {
+                flash_loan: true,
+                ..Pause::default()
+            }),
+        )
+        .unwrap();
+        receiver
+            .flash_loan(
+                &mut app,
+                &owner,
+                &vault,
+                Uint128::from(50_000u128),
+                Uint128::from(500u128),
+            )
+            .unwrap_err();
+    }
+
+    #[test]
+    fn borrow_shares() {
+        let mut app = mock_rujira_app();
+        let owner = app.api().addr_make("owner");
+        let borrower = app.api().addr_make("borrower");
+        app.init_modules(|router, _, storage| {
+            router
+                .bank
+                .init_balance(storage, &owner, coins(1_000, "btc"))
+                .unwrap();
+            router
+                .bank
+                .init_balance(storage, &borrower, coins(1_000, "btc"))
+                .unwrap();
+        });
+        let contract = setup(&mut app, Decimal::zero(), &owner);
+
+        app.execute_contract(
+            owner.clone(),
+            contract.clone(),
+            &ExecuteMsg::Deposit {
+                callback: None,
+                min_shares: None,
+                deadline: None,
+            },
+            &coins(1_000u128, "btc"),
+        )
+        .unwrap();
+        app.wasm_sudo(
+            contract.clone(),
+            &SudoMsg::SetBorrower {
+                contract: borrower.to_string(),
+                limit: Uint128::from(1_000u128),
+            },
+        )
+        .unwrap();
+        app.execute_contract(
+            borrower.clone(),
+            contract.clone(),
+            &ExecuteMsg::Market(MarketMsg::BorrowShares {
+                shares: Uint128::from(400u128),
+                callback: None,
+                delegate: None,
+            }),
+            &[],
+        )
+        .unwrap();
+
+        // A year at 15% takes the 400 shares to 460
+        app.update_block(|x| x.time = x.time.plus_days(365));
+
+        // Exactly the shares asked for are issued, for their value rounded down
+        let res = app
+            .execute_contract(
+                borrower.clone(),
+                contract.clone(),
+                &ExecuteMsg::Market(MarketMsg::BorrowShares {
+                    shares: Uint128::from(7u128),
+                    callback: None,
+                    delegate: None,
+                }),
+                &[],
+            )
+            .unwrap();
+        res.assert_event(
+            &Event::new("wasm-rujira-ghost-vault/borrow")
+                .add_attributes(vec![("amount", "8"), ("shares", "7")]),
+        );
+        let res: BorrowerResponse = app
+            .wrap()
+            .query_wasm_smart(
+                contract.clone(),
+                &QueryMsg::Borrower {
+                    addr: borrower.to_string(),
+                },
+            )
+            .unwrap();
+        assert_eq!(res.shares, Uint128::from(407u128));
+        assert_eq!(res.current, Uint128::from(468u128));
+
+        app.execute_contract(
+            borrower.clone(),
+            contract.clone(),
+            &ExecuteMsg::Market(MarketMsg::RepayShares {
+                shares: Uint128::from(1_000u128),
+                delegate: None,
+            }),
+            &coins(467u128, "btc"),
+        )
+        .unwrap_err();
+        app.execute_contract(
+            borrower.clone(),
+            contract.clone(),
+            &ExecuteMsg::Market(MarketMsg::RepayShares {
+                shares: Uint128::from(1_000u128),
+                delegate: None,
+            }),
+            &coins(500u128, "btc"),
+        )
+        .unwrap();
+
+        // Capped at the debt, with the surplus refunded
+        let balance = app.wrap().query_balance(&borrower, "btc").unwrap();
+        assert_eq!(balance.amount, Uint128::from(940u128));
+        let res: BorrowerResponse = app
+            .wrap()
+            .query_wasm_smart(
+                contract.clone(),
+                &QueryMsg::Borrower {
+                    addr: borrower.to_string(),
+                },
+            )
+            .unwrap();
+        assert_eq!(res.shares, Uint128::zero());
+    }
+
+    #[test]
+    fn delegates() {
+        let mut app = mock_rujira_app();
+        let owner = app.api().addr_make("owner");
+        let borrower = app.api().addr_make("borrower");
+        let alice = app.api().addr_make("alice");
+        let bob = app.api().addr_make("bob");
+        app.init_modules(|router, _, storage| {
+            router
+                .bank
+                .init_balance(storage, &owner, coins(1_000, "btc"))
+                .unwrap();
+        });
+        let contract = setup(&mut app, Decimal::zero(), &owner);
+
+        app.execute_contract(
+            owner.clone(),
+            contract.clone(),
+            &ExecuteMsg::Deposit {
+                callback: None,
+                min_shares: None,
+                deadline: None,
+            },
+            &coins(1_000u128, "btc"),
+        )
+        .unwrap();
+        app.wasm_sudo(
+            contract.clone(),
+            &SudoMsg::SetBorrower {
+                contract: borrower.to_string(),
+                limit: Uint128::from(1_000u128),
+            },
+        )
+        .unwrap();
+        for (amount, delegate) in [
+            (100u128, None),
+            (200u128, Some(alice.to_string())),
+            (300u128, Some(bob.to_string())),
+        ] {
+            app.execute_contract(
+                borrower.clone(),
+                contract.clone(),
+                &ExecuteMsg::Market(MarketMsg::Borrow {
+                    callback: None,
+                    amount: Uint128::from(amount),
+                    delegate,
+                }),
+                &[],
+            )
+            .unwrap();
+        }
+
+        let res: DelegatesResponse = app
+            .wrap()
+            .query_wasm_smart(
+                contract.clone(),
+                &QueryMsg::Delegates {
+                    borrower: borrower.to_string(),
+                    limit: Some(1),
+                    start_after: None,
+                },
+            )
+            .unwrap();
+        assert_eq!(res.delegates.len(), 1);
+        // Totals cover every delegate, not just the page
+        assert_eq!(res.delegated_shares, Uint128::from(500u128));
+        assert_eq!(res.delegated, Uint128::from(500u128));
+        assert_eq!(res.undelegated_shares, Uint128::from(100u128));
+        assert_eq!(
+            res.delegated_shares + res.undelegated_shares,
+            res.borrower.shares
+        );
+
+        let next: DelegatesResponse = app
+            .wrap()
+            .query_wasm_smart(
+                contract.clone(),
+                &QueryMsg::Delegates {
+                    borrower: borrower.to_string(),
+                    limit: None,
+                    start_after: Some(res.delegates[0].addr.clone()),
+                },
+            )
+            .unwrap();
+        assert_eq!(next.delegates.len(), 1);
+        let mut delegates = [res.delegates[0].clone(), next.delegates[0].clone()];
+        delegates.sort_by_key(|x| x.shares);
+        assert_eq!(delegates[0].addr, alice.to_string());
+        assert_eq!(delegates[0].current, Uint128::from(200u128));
+        assert_eq!(delegates[1].addr, bob.to_string());
+        assert_eq!(delegates[1].current, Uint128::from(300u128));
+
+        // Repaying without a delegate clears the undelegated debt first, then reduces each
+        // delegate's in proportion
+        app.execute_contract(
+            borrower.clone(),
+            contract.clone(),
+            &ExecuteMsg::Market(MarketMsg::Repay { delegate: None }),
+            &coins(350u128, "btc"),
+        )
+        .unwrap();
+        let res: DelegatesResponse = app
+            .wrap()
+            .query_wasm_smart(
+                contract.clone(),
+                &QueryMsg::Delegates {
+                    borrower: borrower.to_string(),
+                    limit: None,
+                    start_after: None,
+                },
+            )
+            .unwrap();
+        assert_eq!(res.borrower.shares, Uint128::from(250u128));
+        assert_eq!(res.undelegated_shares, Uint128::zero());
+        let mut delegates = res.delegates.clone();
+        delegates.sort_by_key(|x| x.shares);
+        assert_eq!(delegates[0].current, Uint128::from(100u128));
+        assert_eq!(delegates[1].current, Uint128::from(150u128));
+    }
+
+    #[test]
+    fn delegate_limit() {
+        let mut app = mock_rujira_app();
+        let owner = app.api().addr_make("owner");
+        let borrower = app.api().addr_make("borrower");
+        let alice = app.api().addr_make("alice");
+        app.init_modules(|router, _, storage| {
+            router
+                .bank
+                .init_balance(storage, &owner, coins(1_000, "btc"))
+                .unwrap();
+        });
+        let contract = setup(&mut app, Decimal::zero(), &owner);
+
+        app.execute_contract(
+            owner.clone(),
+            contract.clone(),
+            &ExecuteMsg::Deposit {
+                callback: None,
+                min_shares: None,
+                deadline: None,
+            },
+            &coins(1_000u128, "btc"),
+        )
+        .unwrap();
+        app.wasm_sudo(
+            contract.clone(),
+            &SudoMsg::SetBorrower {
+                contract: borrower.to_string(),
+                limit: Uint128::from(1_000u128),
+            },
+        )
+        .unwrap();
+        app.execute_contract(
+            borrower.clone(),
+            contract.clone(),
+            &ExecuteMsg::Market(MarketMsg::SetDelegateLimit {
+                delegate: alice.to_string(),
+                limit: Some(Uint128::from(300u128)),
+            }),
+            &[],
+        )
+        .unwrap();
+
+        let borrow = |amount: u128, delegate: &Addr| {
+            ExecuteMsg::Market(MarketMsg::Borrow {
+                callback: None,
+                amount: Uint128::from(amount),
+                delegate: Some(delegate.to_string()),
+            })
+        };
+        app.execute_contract(
+            borrower.clone(),
+            contract.clone(),
+            &borrow(200, &alice),
+            &[],
+        )
+        .unwrap();
+        app.execute_contract(
+            borrower.clone(),
+            contract.clone(),
+            &borrow(101, &alice),
+            &[],
+        )
+        .unwrap_err();
+        app.execute_contract(
+            borrower.clone(),
+            contract.clone(),
+            &borrow(100, &alice),
+            &[],
+        )
+        .unwrap();
+
+        let res: DelegateResponse = app
+            .wrap()
+            .query_wasm_smart(
+                contract.clone(),
+                &QueryMsg::Delegate {
+                    borrower: borrower.to_string(),
+                    addr: alice.to_string(),
+                },
+            )
+            .unwrap();
+        assert_eq!(res.current, Uint128::from(300u128));
+        let res: DelegatesResponse = app
+            .wrap()
+            .query_wasm_smart(
+                contract.clone(),
+                &QueryMsg::Delegates {
+                    borrower: borrower.to_string(),
+                    limit: None,
+                    start_after: None,
+                },
+            )
+            .unwrap();
+        assert_eq!(res.delegates[0].limit, Some(Uint128::from(300u128)));
+
+        // Lifting the cap leaves only the borrower's limit
+        app.execute_contract(
+            borrower.clone(),
+            contract.clone(),
+            &ExecuteMsg::Market(MarketMsg::SetDelegateLimit {
+                delegate: alice.to_string(),
+                limit: None,
+            }),
+            &[],
+        )
+        .unwrap();
+        app.execute_contract(
+            borrower.clone(),
+            contract.clone(),
+            &borrow(500, &alice),
+            &[],
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn transfer_debt() {
+        let mut app = mock_rujira_app();
+        let owner = app.api().addr_make("owner");
+        let market = app.api().addr_make("market");
+        let successor = app.api().addr_make("successor");
+        let alice = app.api().addr_make("alice");
+        let bob = app.api().addr_make("bob");
+        app.init_modules(|router, _, storage| {
+            router
+                .bank
+                .init_balance(storage, &owner, coins(1_000, "btc"))
+                .unwrap();
+        });
+        let contract = setup(&mut app, Decimal::zero(), &owner);
+
+        app.execute_contract(
+            owner.clone(),
+            contract.clone(),
+            &ExecuteMsg::Deposit {
+                callback: None,
+                min_shares: None,
+                deadline: None,
+            },
+            &coins(1_000u128, "btc"),
+        )
+        .unwrap();
+        for (contract_addr, limit) in [(&market, 1_000u128), (&successor, 200u128)] {
+            app.wasm_sudo(
+                contract.clone(),
+                &SudoMsg::SetBorrower {
+                    contract: contract_addr.to_string(),
+                    limit: Uint128::from(limit),
+                },
+            )
+            .unwrap();
+        }
+        for (amount, delegate) in [(100u128, None), (200u128, Some(alice.to_string()))] {
+            app.execute_contract(
+                market.clone(),
+                contract.clone(),
+                &ExecuteMsg::Market(MarketMsg::Borrow {
+                    callback: None,
+                    amount: Uint128::from(amount),
+                    delegate,
+                }),
+                &[],
+            )
+            .unwrap();
+        }
+
+        let transfer = |from: Option<&Addr>, to: Option<&Addr>, shares: u128| {
+            ExecuteMsg::Market(MarketMsg::TransferDebt {
+                from_delegate: from.map(|x| x.to_string()),
+                to_delegate: to.map(|x| x.to_string()),
+                shares: Uint128::from(shares),
+            })
+        };
+        app.execute_contract(
+            market.clone(),
+            contract.clone(),
+            &transfer(None, Some(&bob), 100),
+            &[],
+        )
+        .unwrap();
+        // Nothing left without a delegate
+        app.execute_contract(
+            market.clone(),
+            contract.clone(),
+            &transfer(None, Some(&bob), 1),
+            &[],
+        )
+        .unwrap_err();
+        app.execute_contract(
+            market.clone(),
+            contract.clone(),
+            &transfer(Some(&alice), None, 50),
+            &[],
+        )
+        .unwrap();
+
+        let res: DelegatesResponse = app
+            .wrap()
+            .query_wasm_smart(
+                contract.clone(),
+                &QueryMsg::Delegates {
+                    borrower: market.to_string(),
+                    limit: None,
+                    start_after: None,
+                },
+            )
+            .unwrap();
+        assert_eq!(res.borrower.shares, Uint128::from(300u128));
+        assert_eq!(res.delegated_shares, Uint128::from(250u128));
+        assert_eq!(res.undelegated_shares, Uint128::from(50u128));
+
+        // The successor's limit is too small for the whole book
+        let migrate = SudoMsg::MigrateBorrowerDebt {
+            from: market.to_string(),
+            to: successor.to_string(),
+        };
+        app.wasm_sudo(contract.clone(), &migrate).unwrap_err();
+        app.wasm_sudo(
+            contract.clone(),
+            &SudoMsg::SetBorrower {
+                contract: successor.to_string(),
+                limit: Uint128::from(1_000u128),
+            },
+        )
+        .unwrap();
+
+        // Bob's debt must fit within the successor's cap on him, and alice's cap moves with her
+        let cap = |delegate: &Addr, limit: Option<u128>| {
+            ExecuteMsg::Market(MarketMsg::SetDelegateLimit {
+                delegate: delegate.to_string(),
+                limit: limit.map(Uint128::from),
+            })
+        };
+        app.execute_contract(
+            market.clone(),
+            contract.clone(),
+            &cap(&alice, Some(200)),
+            &[],
+        )
+        .unwrap();
+        app.execute_contract(
+            successor.clone(),
+            contract.clone(),
+            &cap(&bob, Some(50)),
+            &[],
+        )
+        .unwrap();
+        app.wasm_sudo(contract.clone(), &migrate).unwrap_err();
+        app.execute_contract(successor.clone(), contract.clone(), &cap(&bob, None), &[])
+            .unwrap();
+        app.wasm_sudo(contract.clone(), &migrate).unwrap();
+
+        let res: DelegatesResponse = app
+            .wrap()
+            .query_wasm_smart(
+                contract.clone(),
+                &QueryMsg::Delegates {
+                    borrower: successor.to_string(),
+                    limit: None,
+                    start_after: None,
+                },
+            )
+            .unwrap();
+        assert_eq!(res.borrower.shares, Uint128::from(300u128));
+        assert_eq!(res.delegated_shares, Uint128::from(250u128));
+        assert_eq!(res.delegates.len(), 2);
+        for delegate in res.delegates {
+            let limit = (delegate.addr == alice.to_string()).then(|| Uint128::from(200u128));
+            assert_eq!(delegate.limit, limit);
+        }
+        let res: DelegatesResponse = app
+            .wrap()
+            .query_wasm_smart(
+                contract.clone(),
+                &QueryMsg::Delegates {
+                    borrower: market.to_string(),
+                    limit: None,
+                    start_after: None,
+                },
+            )
+            .unwrap();
+        assert_eq!(res.borrower.shares, Uint128::zero());
+        assert!(res.delegates.is_empty());
+    }
+
+    #[test]
+    fn wind_down() {
+        let mut app = mock_rujira_app();
+        let owner = app.api().addr_make("owner");
+        let market = app.api().addr_make("market");
+        app.init_modules(|router, _, storage| {
+            router
+                .bank
+                .init_balance(storage, &owner, coins(1_000, "btc"))
+                .unwrap();
+            router
+                .bank
+                .init_balance(storage, &market, coins(110, "btc"))
+                .unwrap();
+        });
+        let contract = setup(&mut app, Decimal::zero(), &owner);
+        app.wasm_sudo(
+            contract.clone(),
+            &SudoMsg::SetInterest(InterestModel::Fixed(Fixed {
+                rate: Decimal::zero(),
+            })),
+        )
+        .unwrap();
+        app.wasm_sudo(
+            contract.clone(),
+            &SudoMsg::SetBorrower {
+                contract: market.to_string(),
+                limit: Uint128::from(1_000u128),
+            },
+        )
+        .unwrap();
+        app.execute_contract(
+            owner.clone(),
+            contract.clone(),
+            &ExecuteMsg::Deposit {
+                callback: None,
+                min_shares: None,
+                deadline: None,
+            },
+            &coins(1_000u128, "btc"),
+        )
+        .unwrap();
+        let borrow = ExecuteMsg::Market(MarketMsg::Borrow {
+            callback: None,
+            amount: Uint128::from(100u128),
+            delegate: None,
+        });
+        app.execute_contract(market.clone(), contract.clone(), &borrow, &[])
+            .unwrap();
+
+        let status = BorrowerStatus::WindDown {
+            premium: Decimal::percent(10),
+        };
+        app.wasm_sudo(
+            contract.clone(),
+            &SudoMsg::SetBorrowerStatus {
+                contract: market.to_string(),
+                status: status.clone(),
+            },
+        )
+        .unwrap();
+        let res: BorrowerResponse = app
+            .wrap()
+            .query_wasm_smart(
+                contract.clone(),
+                &QueryMsg::Borrower {
+                    addr: market.to_string(),
+                },
+            )
+            .unwrap();
+        assert_eq!(res.available, Uint128::zero());
+        let res: BorrowerTermsResponse = app
+            .wrap()
+            .query_wasm_smart(
+                contract.clone(),
+                &QueryMsg::BorrowerTerms {
+                    addr: market.to_string(),
+                },
+            )
+            .unwrap();
+        assert_eq!(res.status, status);
+
+        // No new borrows, and the borrower can't be removed while it holds debt
+        app.execute_contract(market.clone(), contract.clone(), &borrow, &[])
+            .unwrap_err();
+        let remove = SudoMsg::RemoveBorrower {
+            contract: market.to_string(),
+        };
+        app.wasm_sudo(contract.clone(), &remove).unwrap_err();
+
+        // The premium is charged on top of the pool's rate
+        app.update_block(|x| x.time = x.time.plus_days(365));
+        let res: BorrowerResponse = app
+            .wrap()
+            .query_wasm_smart(
+                contract.clone(),
+                &QueryMsg::Borrower {
+                    addr: market.to_string(),
+                },
+            )
+            .unwrap();
+        assert_eq!(res.current, Uint128::from(110u128));
+
+        app.execute_contract(
+            market.clone(),
+            contract.clone(),
+            &ExecuteMsg::Market(MarketMsg::Repay { delegate: None }),
+            &coins(110u128, "btc"),
+        )
+        .unwrap();
+        let res: StatusResponse = app
+            .wrap()
+            .query_wasm_smart(contract.clone(), &QueryMsg::Status {})
+            .unwrap();
+        assert_eq!(res.debt_pool.size, Uint128::zero());
+        assert_eq!(res.deposit_pool.size, Uint128::from(1_010u128));
+
+        app.wasm_sudo(contract.clone(), &remove).unwrap();
+        app.wrap()
+            .query_wasm_smart::<BorrowerResponse>(
+                contract.clone(),
+                &QueryMsg::Borrower {
+                    addr: market.to_string(),
+                },
+            )
+            .unwrap_err();
+    }
+
+    #[test]
+    fn wind_down_delegate() {
+        let mut app = mock_rujira_app();
+        let owner = app.api().addr_make("owner");
+        let market = app.api().addr_make("market");
+        let account = app.api().addr_make("account");
+        app.init_modules(|router, _, storage| {
+            router
+                .bank
+                .init_balance(storage, &owner, coins(1_000, "btc"))
+                .unwrap();
+            router
+                .bank
+                .init_balance(storage, &account, coins(120, "btc"))
+                .unwrap();
+        });
+        let contract = setup(&mut app, Decimal::zero(), &owner);
+        app.wasm_sudo(
+            contract.clone(),
+            &SudoMsg::SetInterest(InterestModel::Fixed(Fixed {
+                rate: Decimal::zero(),
+            })),
+        )
+        .unwrap();
+        app.wasm_sudo(
+            contract.clone(),
+            &SudoMsg::SetBorrower {
+                contract: market.to_string(),
+                limit: Uint128::from(1_000u128),
+            },
+        )
+        .unwrap();
+        app.execute_contract(
+            owner.clone(),
+            contract.clone(),
+            &ExecuteMsg::Deposit {
+                callback: None,
+                min_shares: None,
+                deadline: None,
+            },
+            &coins(1_000u128, "btc"),
+        )
+        .unwrap();
+        app.execute_contract(
+            market.clone(),
+            contract.clone(),
+            &ExecuteMsg::Market(MarketMsg::Borrow {
+                callback: None,
+                amount: Uint128::from(100u128),
+                delegate: Some(account.to_string()),
+            }),
+            &[],
+        )
+        .unwrap();
+        app.wasm_sudo(
+            contract.clone(),
+            &SudoMsg::SetBorrowerStatus {
+                contract: market.to_string(),
+                status: BorrowerStatus::WindDown {
+                    premium: Decimal::percent(10),
+                },
+            },
+        )
+        .unwrap();
+
+        // The premium is owed by the delegate whose debt it is charged on
+        app.update_block(|x| x.time = x.time.plus_days(365));
+        let delegates = |app: &RujiraApp| -> DelegatesResponse {
+            app.wrap()
+                .query_wasm_smart(
+                    contract.clone(),
+                    &QueryMsg::Delegates {
+                        borrower: market.to_string(),
+                        limit: None,
+                        start_after: None,
+                    },
+                )
+                .unwrap()
+        };
+        let res = delegates(&app);
+        assert_eq!(res.delegated, Uint128::from(110u128));
+        assert_eq!(res.undelegated_shares, Uint128::zero());
+
+        // Repaying the delegate in full clears the borrower's debt
+        app.execute_contract(
+            account.clone(),
+            contract.clone(),
+            &ExecuteMsg::RepayFor {
+                borrower: market.to_string(),
+                delegate: Some(account.to_string()),
+            },
+            &coins(110u128, "btc"),
+        )
+        .unwrap();
+        let res = delegates(&app);
+        assert_eq!(res.borrower.shares, Uint128::zero());
+        assert_eq!(res.undelegated_shares, Uint128::zero());
+        assert!(res.delegates.is_empty());
+        let balance = app.wrap().query_balance(&account, "btc").unwrap();
+        assert_eq!(balance.amount, Uint128::from(10u128));
+        let res: StatusResponse = app
+            .wrap()
+            .query_wasm_smart(contract.clone(), &QueryMsg::Status {})
+            .unwrap();
+        assert_eq!(res.debt_pool.size, Uint128::zero());
+        assert_eq!(res.deposit_pool.size, Uint128::from(1_010u128));
+    }
+
+    #[test]
+    fn deposit_limit() {
+        let mut app = mock_rujira_app();
+        let owner = app.api().addr_make("owner");
+        let market = app.api().addr_make("market");
+        app.init_modules(|router, _, storage| {
+            router
+                .bank
+                .init_balance(storage, &owner, coins(2_000, "btc"))
+                .unwrap();
+        });
+        let contract = setup(&mut app, Decimal::zero(), &owner);
+        let deposit = ExecuteMsg::Deposit {
+            callback: None,
+            min_shares: None,
+            deadline: None,
+        };
+        app.execute_contract(
+            owner.clone(),
+            contract.clone(),
+            &deposit,
+            &coins(1_000u128, "btc"),
+        )
+        .unwrap();
+        app.wasm_sudo(
+            contract.clone(),
+            &SudoMsg::SetBorrower {
+                contract: market.to_string(),
+                limit: Uint128::zero(),
+            },
+        )
+        .unwrap();
+        let set_limit = |fraction: Decimal| SudoMsg::SetBorrowerDepositLimit {
+            contract: market.to_string(),
+            limit: Some(DepositLimit {
+                fraction,
+                floor: None,
+                ceiling: Some(Uint128::from(300u128)),
+            }),
+        };
+        app.wasm_sudo(contract.clone(), &set_limit(Decimal::percent(200)))
+            .unwrap_err();
+        app.wasm_sudo(contract.clone(), &set_limit(Decimal::percent(20)))
+            .unwrap();
+
+        let query = |app: &RujiraApp| -> BorrowerResponse {
+            app.wrap()
+                .query_wasm_smart(
+                    contract.clone(),
+                    &QueryMsg::Borrower {
+                        addr: market.to_string(),
+                    },
+                )
+                .unwrap()
+        };
+        assert_eq!(query(&app).limit, Uint128::from(200u128));
+
+        let borrow = |amount: u128| {
+            ExecuteMsg::Market(MarketMsg::Borrow {
+                callback: None,
+                amount: Uint128::from(amount),
+                delegate: None,
+            })
+        };
+        app.execute_contract(market.clone(), contract.clone(), &borrow(201), &[])
+            .unwrap_err();
+        app.execute_contract(market.clone(), contract.clone(), &borrow(200), &[])
+            .unwrap();
+
+        // The limit grows with deposits, up to the ceiling
+        app.execute_contract(
+            owner.clone(),
+            contract.clone(),
+            &deposit,
+            &coins(1_000u128, "btc"),
+        )
+        .unwrap();
+        let res = query(&app);
+        assert_eq!(res.limit, Uint128::from(300u128));
+        assert_eq!(res.available, Uint128::from(100u128));
+        app.execute_contract(market.clone(), contract.clone(), &borrow(101), &[])
+            .unwrap_err();
+        app.execute_contract(market.clone(), contract.clone(), &borrow(100), &[])
+            .unwrap();
+
+        // Clearing it restores the absolute limit
+        app.wasm_sudo(
+            contract.clone(),
+            &SudoMsg::SetBorrowerDepositLimit {
+                contract: market.to_string(),
+                limit: None,
+            },
+        )
+        .unwrap();
+        assert_eq!(query(&app).limit, Uint128::zero());
+        let res: BorrowerTermsResponse = app
+            .wrap()
+            .query_wasm_smart(
+                contract.clone(),
+                &QueryMsg::BorrowerTerms {
+                    addr: market.to_string(),
+                },
+            )
+            .unwrap();
+        assert_eq!(res.deposit_limit, None);
+    }
+
+    #[test]
+    fn borrower_spread() {
+        let mut app = mock_rujira_app();
+        let owner = app.api().addr_make("owner");
+        let market = app.api().addr_make("market");
+        app.init_modules(|router, _, storage| {
+            router
+                .bank
+                .init_balance(storage, &owner, coins(1_010, "btc"))
+                .unwrap();
+        });
+        let contract = setup(&mut app, Decimal::zero(), &owner);
+        app.wasm_sudo(
+            contract.clone(),
+            &SudoMsg::SetInterest(InterestModel::Fixed(Fixed {
+                rate: Decimal::percent(10),
+            })),
+        )
+        .unwrap();
+        app.wasm_sudo(
+            contract.clone(),
+            &SudoMsg::SetBorrower {
+                contract: market.to_string(),
+                limit: Uint128::from(1_000u128),
+            },
+        )
+        .unwrap();
+        app.execute_contract(
+            owner.clone(),
+            contract.clone(),
+            &ExecuteMsg::Deposit {
+                callback: None,
+                min_shares: None,
+                deadline: None,
+            },
+            &coins(1_000u128, "btc"),
+        )
+        .unwrap();
+        let account = app.api().addr_make("account");
+        for (amount, delegate) in [(60u128, None), (40u128, Some(account.to_string()))] {
+            app.execute_contract(
+                market.clone(),
+                contract.clone(),
+                &ExecuteMsg::Market(MarketMsg::Borrow {
+                    callback: None,
+                    amount: Uint128::from(amount),
+                    delegate,
+                }),
+                &[],
+            )
+            .unwrap();
+        }
+        let set_spread = |spread: Decimal| SudoMsg::SetBorrowerSpread {
+            contract: market.to_string(),
+            spread,
+        };
+        app.wasm_sudo(contract.clone(), &set_spread(Decimal::percent(5)))
+            .unwrap();
+
+        let query = |app: &RujiraApp| -> BorrowerResponse {
+            app.wrap()
+                .query_wasm_smart(
+                    contract.clone(),
+                    &QueryMsg::Borrower {
+                        addr: market.to_string(),
+                    },
+                )
+                .unwrap()
+        };
+        let terms = |app: &RujiraApp| -> BorrowerTermsResponse {
+            app.wrap()
+                .query_wasm_smart(
+                    contract.clone(),
+                    &QueryMsg::BorrowerTerms {
+                        addr: market.to_string(),
+                    },
+                )
+                .unwrap()
+        };
+        let res = terms(&app);
+        assert_eq!(res.spread, Decimal::percent(5));
+        assert_eq!(res.rate, Decimal::percent(15));
+        // Depositors earn the spread on top of the pool's rate
+        let res: StatusResponse = app
+            .wrap()
+            .query_wasm_smart(contract.clone(), &QueryMsg::Status {})
+            .unwrap();
+        assert_eq!(res.lend_rate, Decimal::permille(15));
+
+        // The spread is charged on the debt after the pool's interest, split across the
+        // borrower's delegates in proportion to their debt
+        app.update_block(|x| x.time = x.time.plus_days(365));
+        assert_eq!(query(&app).current, Uint128::from(115u128));
+        let res: DelegatesResponse = app
+            .wrap()
+            .query_wasm_smart(
+                contract.clone(),
+                &QueryMsg::Delegates {
+                    borrower: market.to_string(),
+                    limit: None,
+                    start_after: None,
+                },
+            )
+            .unwrap();
+        assert_eq!(res.delegated, Uint128::from(46u128));
+        assert_eq!(res.undelegated, Uint128::from(68u128));
+        assert_eq!(
+            res.delegated_shares + res.undelegated_shares,
+            res.borrower.shares
+        );
+
+        // It is charged as interest accrues, ahead of any new deposit
+        let res = app
+            .execute_contract(
+                owner.clone(),
+                contract.clone(),
+                &ExecuteMsg::Deposit {
+                    callback: None,
+                    min_shares: None,
+                    deadline: None,
+                },
+                &coins(10u128, "btc"),
+            )
+            .unwrap();
+        res.assert_event(
+            &Event::new("wasm-rujira-ghost-vault/accrue").add_attribute("premium", "5"),
+        );
+
+        // Removing the spread settles what is owed, crediting it to depositors
+        app.wasm_sudo(contract.clone(), &set_spread(Decimal::zero()))
+            .unwrap();
+        assert_eq!(query(&app).current, Uint128::from(115u128));
+        assert_eq!(terms(&app).rate, Decimal::percent(10));
+        let res: StatusResponse = app
+            .wrap()
+            .query_wasm_smart(contract.clone(), &QueryMsg::Status {})
+            .unwrap();
+        assert_eq!(res.debt_pool.size, Uint128::from(115u128));
+        assert_eq!(res.deposit_pool.size, Uint128::from(1_025u128));
+    }
+
+    #[test]
+    fn premium_compounding() {
+        let mut app = mock_rujira_app();
+        let owner = app.api().addr_make("owner");
+        let market = app.api().addr_make("market");
+        app.init_modules(|router, _, storage| {
+            router
+                .bank
+                .init_balance(storage, &owner, coins(10_000, "btc"))
+                .unwrap();
+        });
+        let contract = setup(&mut app, Decimal::zero(), &owner);
+        app.wasm_sudo(
+            contract.clone(),
+            &SudoMsg::SetInterest(InterestModel::Fixed(Fixed {
+                rate: Decimal::zero(),
+            })),
+        )
+        .unwrap();
+        app.wasm_sudo(contract.clone(), &SudoMsg::SetCompounding(true))
+            .unwrap();
+        app.wasm_sudo(
+            contract.clone(),
+            &SudoMsg::SetBorrower {
+                contract: market.to_string(),
+                limit: Uint128::from(10_000u128),
+            },
+        )
+        .unwrap();
+        app.execute_contract(
+            owner.clone(),
+            contract.clone(),
+            &ExecuteMsg::Deposit {
+                callback: None,
+                min_shares: None,
+                deadline: None,
+            },
+            &coins(10_000u128, "btc"),
+        )
+        .unwrap();
+        app.execute_contract(
+            market.clone(),
+            contract.clone(),
+            &ExecuteMsg::Market(MarketMsg::Borrow {
+                callback: None,
+                amount: Uint128::from(1_000u128),
+                delegate: None,
+            }),
+            &[],
+        )
+        .unwrap();
+        app.wasm_sudo(
+            contract.clone(),
+            &SudoMsg::SetBorrowerSpread {
+                contract: market.to_string(),
+                spread: Decimal::percent(10),
+            },
+        )
+        .unwrap();
+
+        // Charged continuously, as the pool's interest is: 1000 * (e^0.1 - 1)
+        app.update_block(|x| x.time = x.time.plus_days(365));
+        let res: BorrowerResponse = app
+            .wrap()
+            .query_wasm_smart(
+                contract.clone(),
+                &QueryMsg::Borrower {
+                    addr: market.to_string(),
+                },
+            )
+            .unwrap();
+        assert_eq!(res.current, Uint128::from(1_105u128));
+    }
+
+    #[test]
+    fn migrate_baseline() {
+        use cosmwasm_schema::cw_serde;
+        use cosmwasm_std::testing::{mock_dependencies, mock_env};
+        use cosmwasm_std::{from_json, Timestamp};
+        use cw_storage_plus::{Item, Map};
+        use rujira_rs::SharePool;
+
+        // Storage as written by 1.0.2
+        #[cw_serde]
+        struct BaselineConfig {
+            denom: String,
+            interest: Interest,
+            fee: Decimal,
+            fee_address: Addr,
+        }
+        #[cw_serde]
+        struct BaselineState {
+            last_updated: Timestamp,
+            debt_pool: SharePool,
+            deposit_pool: SharePool,
+        }
+        #[cw_serde]
+        struct BaselineBorrower {
+            addr: Addr,
+            limit: Uint128,
+            shares: Uint128,
+        }
+
+        let mut deps = mock_dependencies();
+        let env = mock_env();
+        let borrower = deps.api.addr_make("borrower");
+        let delegate = deps.api.addr_make("delegate");
+        let fees = deps.api.addr_make("fees");
+        set_contract_version(deps.as_mut().storage, CONTRACT_NAME, "1.0.2").unwrap();
+        Item::<BaselineConfig>::new("config")
+            .save(
+                deps.as_mut().storage,
+                &BaselineConfig {
+                    denom: "btc".to_string(),
+                    interest: Interest {
+                        target_utilization: Decimal::percent(80),
+                        base_rate: Decimal::percent(3),
+                        step1: Decimal::percent(20),
+                        step2: Decimal::percent(100),
+                    },
+                    fee: Decimal::percent(10),
+                    fee_address: fees,
+                },
+            )
+            .unwrap();
+        let mut debt_pool = SharePool::default();
+        debt_pool.join(Uint128::from(300u128)).unwrap();
+        let mut deposit_pool = SharePool::default();
+        deposit_pool.join(Uint128::from(1_000u128)).unwrap();
+        Item::<BaselineState>::new("state")
+            .save(
+                deps.as_mut().storage,
+                &BaselineState {
+                    last_updated: env.block.time,
+                    debt_pool,
+                    deposit_pool,
+                },
+            )
+            .unwrap();
+        Map::<Addr, BaselineBorrower>::new("borrowers")
+            .save(
+                deps.as_mut().storage,
+                borrower.clone(),
+                &BaselineBorrower {
+                    addr: borrower.clone(),
+                    limit: Uint128::from(1_000u128),
+                    shares: Uint128::from(300u128),
+                },
+            )
+            .unwrap();
+        Map::<(Addr, Addr), Uint128>::new("delegates")
+            .save(
+                deps.as_mut().storage,
+                (borrower.clone(), delegate.clone()),
+                &Uint128::from(100u128),
+            )
+            .unwrap();
+
+        migrate(deps.as_mut(), env.clone(), ()).unwrap();
+        assert_eq!(
+            get_contract_version(deps.as_ref().storage).unwrap().version,
+            CONTRACT_VERSION
+        );
+
+        // The delegate's shares carry over as its units of the borrower's debt
+        let res: DelegatesResponse = from_json(
+            query(
+                deps.as_ref(),
+                env.clone(),
+                QueryMsg::Delegates {
+                    borrower: borrower.to_string(),
+                    limit: None,
+                    start_after: None,
+                },
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(res.borrower.shares, Uint128::from(300u128));
+        assert_eq!(res.delegated_shares, Uint128::from(100u128));
+        assert_eq!(res.undelegated_shares, Uint128::from(200u128));
+        let res: ConfigResponse =
+            from_json(query(deps.as_ref(), env.clone(), QueryMsg::Config {}).unwrap()).unwrap();
+        assert_eq!(res.denom, "btc");
+        let res: StatusResponse =
+            from_json(query(deps.as_ref(), env.clone(), QueryMsg::Status {}).unwrap()).unwrap();
+        assert_eq!(res.debt_pool.size, Uint128::from(300u128));
+        assert_eq!(res.deposit_pool.size, Uint128::from(1_000u128));
+
+        // Migrating back to an older version is rejected
+        set_contract_version(deps.as_mut().storage, CONTRACT_NAME, "9.0.0").unwrap();
+        migrate(deps.as_mut(), env, ()).unwrap_err();
+    }
 }
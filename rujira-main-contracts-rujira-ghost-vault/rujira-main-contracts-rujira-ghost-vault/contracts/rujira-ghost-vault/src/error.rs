@@ -1,5 +1,6 @@
 use cosmwasm_std::{
-    CheckedFromRatioError, ConversionOverflowError, OverflowError, StdError, Uint128,
+    CheckedFromRatioError, CheckedMultiplyFractionError, ConversionOverflowError, OverflowError,
+    StdError, Timestamp, Uint128,
 };
 use cw_utils::PaymentError;
 use rujira_rs::SharePoolError;
@@ -16,6 +17,9 @@ pub enum ContractError {
     #[error("{0}")]
     CheckedFromRatio(#[from] CheckedFromRatioError),
 
+    #[error("{0}")]
+    CheckedMultiplyFraction(#[from] CheckedMultiplyFractionError),
+
     #[error("{0}")]
     ConversionOverflow(#[from] ConversionOverflowError),
 
@@ -34,6 +38,18 @@ pub enum ContractError {
     #[error("BorrowLimitReached {limit}")]
     BorrowLimitReached { limit: Uint128 },
 
+    #[error("DelegateLimitReached {delegate} {limit}")]
+    DelegateLimitReached { delegate: String, limit: Uint128 },
+
+    #[error("InsufficientDebt shares {shares} requested {requested}")]
+    InsufficientDebt { shares: Uint128, requested: Uint128 },
+
+    #[error("WindDown")]
+    WindDown {},
+
+    #[error("BorrowerHasDebt {shares}")]
+    BorrowerHasDebt { shares: Uint128 },
+
     #[error("InsufficientRepay debt {debt} value {value} repaid {repaid}")]
     InsufficientRepay {
         debt: Uint128,
@@ -44,6 +60,45 @@ pub enum ContractError {
     #[error("ExcessiveRepay debt {debt} repaid {repaid}")]
     ExcessiveRepay { debt: Uint128, repaid: Uint128 },
 
+    #[error("Paused {operation}")]
+    Paused { operation: String },
+
+    #[error("DepositCapExceeded cap {cap} deposits {deposits}")]
+    DepositCapExceeded { cap: Uint128, deposits: Uint128 },
+
+    #[error("InsufficientLiquidity available {available} requested {requested}")]
+    InsufficientLiquidity {
+        available: Uint128,
+        requested: Uint128,
+    },
+
+    #[error("SlippageExceeded minimum {minimum} actual {actual}")]
+    SlippageExceeded { minimum: Uint128, actual: Uint128 },
+
+    #[error("DeadlineExceeded {deadline}")]
+    DeadlineExceeded { deadline: Timestamp },
+
+    #[error("InsufficientShares required {required} sent {sent}")]
+    InsufficientShares { required: Uint128, sent: Uint128 },
+
+    #[error("FlashLoanActive")]
+    FlashLoanActive {},
+
+    #[error("FlashLoanLimit limit {limit} requested {requested}")]
+    FlashLoanLimit { limit: Uint128, requested: Uint128 },
+
+    #[error("FlashLoanUnpaid expected {expected} balance {balance}")]
+    FlashLoanUnpaid { expected: Uint128, balance: Uint128 },
+
+    #[error("UnknownTicket {id}")]
+    UnknownTicket { id: u64 },
+
+    #[error("TicketPending {id}")]
+    TicketPending { id: u64 },
+
+    #[error("TicketFilled {id}")]
+    TicketFilled { id: u64 },
+
     #[error("Invalid: {0}")]
     Invalid(String),
     // Add any other custom errors you like here.
@@ -1,4 +1,6 @@
-use cosmwasm_std::{Addr, Event, Uint128};
+use cosmwasm_std::{Addr, Decimal, Event, Uint128};
+
+use crate::{borrowers::BorrowerStatus, state::Accrual};
 
 pub fn event_deposit(owner: Addr, amount: Uint128, shares: Uint128) -> Event {
     Event::new(format!("{}/deposit", env!("CARGO_PKG_NAME")))
@@ -14,6 +16,35 @@ pub fn event_withdraw(owner: Addr, amount: Uint128, shares: Uint128) -> Event {
         .add_attribute("shares", shares)
 }
 
+pub fn event_withdraw_queue(owner: Addr, id: u64, shares: Uint128) -> Event {
+    Event::new(format!("{}/withdraw_queue", env!("CARGO_PKG_NAME")))
+        .add_attribute("owner", owner)
+        .add_attribute("id", id.to_string())
+        .add_attribute("shares", shares)
+}
+
+pub fn event_withdraw_fill(owner: Addr, id: u64, amount: Uint128, shares: Uint128) -> Event {
+    Event::new(format!("{}/withdraw_fill", env!("CARGO_PKG_NAME")))
+        .add_attribute("owner", owner)
+        .add_attribute("id", id.to_string())
+        .add_attribute("amount", amount)
+        .add_attribute("shares", shares)
+}
+
+pub fn event_withdraw_claim(owner: Addr, id: u64, amount: Uint128) -> Event {
+    Event::new(format!("{}/withdraw_claim", env!("CARGO_PKG_NAME")))
+        .add_attribute("owner", owner)
+        .add_attribute("id", id.to_string())
+        .add_attribute("amount", amount)
+}
+
+pub fn event_withdraw_cancel(owner: Addr, id: u64, shares: Uint128) -> Event {
+    Event::new(format!("{}/withdraw_cancel", env!("CARGO_PKG_NAME")))
+        .add_attribute("owner", owner)
+        .add_attribute("id", id.to_string())
+        .add_attribute("shares", shares)
+}
+
 pub fn event_borrow(
     borrower: Addr,
     delegate: Option<String>,
@@ -30,12 +61,121 @@ pub fn event_borrow(
 pub fn event_repay(
     borrower: Addr,
     delegate: Option<String>,
+    payer: Addr,
     amount: Uint128,
     shares: Uint128,
 ) -> Event {
     Event::new(format!("{}/repay", env!("CARGO_PKG_NAME")))
         .add_attribute("borrower", borrower)
+        .add_attribute("payer", payer)
         .add_attribute("delegate", delegate.unwrap_or_default())
         .add_attribute("amount", amount)
         .add_attribute("shares", shares)
 }
+
+pub fn event_delegate_limit(borrower: Addr, delegate: String, limit: Option<Uint128>) -> Event {
+    Event::new(format!("{}/delegate_limit", env!("CARGO_PKG_NAME")))
+        .add_attribute("borrower", borrower)
+        .add_attribute("delegate", delegate)
+        .add_attribute("limit", limit.map(|x| x.to_string()).unwrap_or_default())
+}
+
+pub fn event_transfer_debt(
+    borrower: Addr,
+    from_delegate: Option<String>,
+    to_delegate: Option<String>,
+    shares: Uint128,
+) -> Event {
+    Event::new(format!("{}/transfer_debt", env!("CARGO_PKG_NAME")))
+        .add_attribute("borrower", borrower)
+        .add_attribute("from_delegate", from_delegate.unwrap_or_default())
+        .add_attribute("to_delegate", to_delegate.unwrap_or_default())
+        .add_attribute("shares", shares)
+}
+
+pub fn event_migrate_debt(from: Addr, to: Addr, shares: Uint128) -> Event {
+    Event::new(format!("{}/migrate_debt", env!("CARGO_PKG_NAME")))
+        .add_attribute("from", from)
+        .add_attribute("to", to)
+        .add_attribute("shares", shares)
+}
+
+pub fn event_premium(borrower: Addr, amount: Uint128, fee_shares: Uint128) -> Event {
+    Event::new(format!("{}/premium", env!("CARGO_PKG_NAME")))
+        .add_attribute("borrower", borrower)
+        .add_attribute("amount", amount)
+        .add_attribute("fee_shares", fee_shares)
+}
+
+pub fn event_borrower_status(borrower: Addr, status: &BorrowerStatus) -> Event {
+    let event = Event::new(format!("{}/borrower_status", env!("CARGO_PKG_NAME")))
+        .add_attribute("borrower", borrower);
+    match status {
+        BorrowerStatus::Active => event.add_attribute("status", "active"),
+        BorrowerStatus::WindDown { premium } => event
+            .add_attribute("status", "wind_down")
+            .add_attribute("premium", premium.to_string()),
+    }
+}
+
+pub fn event_borrower_spread(borrower: Addr, spread: Decimal) -> Event {
+    Event::new(format!("{}/borrower_spread", env!("CARGO_PKG_NAME")))
+        .add_attribute("borrower", borrower)
+        .add_attribute("spread", spread.to_string())
+}
+
+pub fn event_remove_borrower(borrower: Addr) -> Event {
+    Event::new(format!("{}/remove_borrower", env!("CARGO_PKG_NAME")))
+        .add_attribute("borrower", borrower)
+}
+
+pub fn event_write_off(
+    borrower: Addr,
+    delegate: Option<String>,
+    amount: Uint128,
+    shares: Uint128,
+    reserve: Uint128,
+) -> Event {
+    Event::new(format!("{}/write_off", env!("CARGO_PKG_NAME")))
+        .add_attribute("borrower", borrower)
+        .add_attribute("delegate", delegate.unwrap_or_default())
+        .add_attribute("amount", amount)
+        .add_attribute("shares", shares)
+        .add_attribute("reserve", reserve)
+}
+
+pub fn event_fund_reserve(sender: Addr, amount: Uint128, shares: Uint128) -> Event {
+    Event::new(format!("{}/fund_reserve", env!("CARGO_PKG_NAME")))
+        .add_attribute("sender", sender)
+        .add_attribute("amount", amount)
+        .add_attribute("shares", shares)
+}
+
+pub fn event_sweep_reserve(to: Addr, shares: Uint128) -> Event {
+    Event::new(format!("{}/sweep_reserve", env!("CARGO_PKG_NAME")))
+        .add_attribute("to", to)
+        .add_attribute("shares", shares)
+}
+
+pub fn event_flash_loan(borrower: Addr, amount: Uint128, fee: Uint128) -> Event {
+    Event::new(format!("{}/flash_loan", env!("CARGO_PKG_NAME")))
+        .add_attribute("borrower", borrower)
+        .add_attribute("amount", amount)
+        .add_attribute("fee", fee)
+}
+
+pub fn event_accrue(accrual: &Accrual, debt_ratio: Decimal, deposit_ratio: Decimal) -> Event {
+    Event::new(format!("{}/accrue", env!("CARGO_PKG_NAME")))
+        .add_attribute("seconds", accrual.seconds.to_string())
+        .add_attribute("debt_rate", accrual.rate.to_string())
+        .add_attribute("interest", accrual.interest)
+        .add_attribute("fee", accrual.fee)
+        .add_attribute("fee_shares", accrual.shares)
+        .add_attribute("premium", accrual.premium)
+        .add_attribute("debt_ratio", debt_ratio.to_string())
+        .add_attribute("deposit_ratio", deposit_ratio.to_string())
+}
+
+pub fn event_config(key: &str, value: impl Into<String>) -> Event {
+    Event::new(format!("{}/config", env!("CARGO_PKG_NAME"))).add_attribute(key, value)
+}
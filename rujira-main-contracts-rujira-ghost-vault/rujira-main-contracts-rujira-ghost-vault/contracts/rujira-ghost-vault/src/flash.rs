@@ -0,0 +1,46 @@
+use crate::ContractError;
+use cosmwasm_schema::cw_serde;
+use cosmwasm_std::{Addr, StdResult, Storage, Uint128};
+use cw_storage_plus::Item;
+use std::ops::Add;
+
+static FLASH_LOAN: Item<FlashLoan> = Item::new("flash_loan");
+
+pub const FLASH_LOAN_REPLY: u64 = 1;
+
+/// A flash loan in progress, settled in the reply to its callback
+#[cw_serde]
+pub struct FlashLoan {
+    pub borrower: Addr,
+    pub amount: Uint128,
+    pub fee: Uint128,
+    /// The vault's balance of the borrowable asset before the loan was sent
+    pub balance: Uint128,
+}
+
+impl FlashLoan {
+    pub fn start(&self, storage: &mut dyn Storage) -> Result<(), ContractError> {
+        Self::ensure_inactive(storage)?;
+        Ok(FLASH_LOAN.save(storage, self)?)
+    }
+
+    /// Rejects other operations whilst a loan is outstanding, so that the loaned funds can't be
+    /// used to repay the vault
+    pub fn ensure_inactive(storage: &dyn Storage) -> Result<(), ContractError> {
+        if FLASH_LOAN.exists(storage) {
+            return Err(ContractError::FlashLoanActive {});
+        }
+        Ok(())
+    }
+
+    pub fn finish(storage: &mut dyn Storage) -> StdResult<Self> {
+        let loan = FLASH_LOAN.load(storage)?;
+        FLASH_LOAN.remove(storage);
+        Ok(loan)
+    }
+
+    /// The balance the vault must hold once the loan is returned
+    pub fn expected(&self) -> Uint128 {
+        self.balance.add(self.fee)
+    }
+}
@@ -0,0 +1,574 @@
+use cosmwasm_schema::cw_serde;
+use cosmwasm_std::{Decimal, Decimal256, OverflowError, StdResult, Timestamp};
+use rujira_rs::ghost::vault::Interest;
+use std::cmp::{max, min};
+
+use crate::ContractError;
+
+pub(crate) const YEAR: u64 = 31_536_000;
+
+/// Bounds on the models' parameters, as percentages
+const MAX_RATE: u64 = 1_000;
+const MAX_STEEPNESS: u64 = 10_000;
+const MAX_SPEED: u64 = 100_000;
+
+/// e^x - 1, summing the Taylor series until its terms fall below the decimal precision.
+/// Errors rather than panics where the growth is too large to represent
+pub fn compound(x: Decimal256) -> Result<Decimal256, OverflowError> {
+    let mut sum = Decimal256::zero();
+    let mut term = x;
+    let mut n = 1u128;
+    while !term.is_zero() {
+        sum = sum.checked_add(term)?;
+        n += 1;
+        term = term.checked_mul(x)? / Decimal256::from_ratio(n, 1u128);
+    }
+    Ok(sum)
+}
+
+/// A curve mapping vault utilization to the annual rate charged on debt
+pub trait InterestRate {
+    fn rate(&self, utilization: Decimal) -> StdResult<Decimal>;
+    fn validate(&self) -> Result<(), ContractError>;
+}
+
+/// The interest rate model used by the vault. Models are untagged so that a bare kink
+/// `Interest`, as used before models were introduced, is still accepted and returned as is
+#[cw_serde]
+#[serde(untagged)]
+pub enum InterestModel {
+    /// Two-slope curve, rising steeply above the target utilization
+    Kink(Interest),
+    /// A constant rate regardless of utilization
+    Fixed(Fixed),
+    /// Linear interpolation between a table of utilization points
+    Piecewise(Piecewise),
+    /// A kink curve that shifts up while utilization is above target and down while below
+    Adaptive(Adaptive),
+}
+
+impl InterestModel {
+    /// The debt rate at `utilization`. `rate_at_target` is the adaptive model's current rate at
+    /// target utilization, `None` until it has first been adjusted
+    pub fn rate(
+        &self,
+        utilization: Decimal,
+        rate_at_target: Option<Decimal>,
+    ) -> StdResult<Decimal> {
+        match self {
+            Self::Kink(x) => InterestRate::rate(x, utilization),
+            Self::Fixed(x) => x.rate(utilization),
+            Self::Piecewise(x) => x.rate(utilization),
+            Self::Adaptive(x) => x
+                .curve(rate_at_target.unwrap_or(x.initial_rate))
+                .rate(utilization),
+        }
+    }
+
+    /// The adaptive model's rate at target, if the model is adaptive
+    pub fn rate_at_target(&self, rate_at_target: Option<Decimal>) -> Option<Decimal> {
+        match self {
+            Self::Adaptive(x) => Some(rate_at_target.unwrap_or(x.initial_rate)),
+            _ => None,
+        }
+    }
+
+    /// The rate to charge over a period of `seconds` held at `utilization`, and the adaptive
+    /// model's rate at target at the end of it
+    pub fn accrue(
+        &self,
+        rate_at_target: Option<Decimal>,
+        utilization: Decimal,
+        seconds: u64,
+    ) -> StdResult<(Decimal, Option<Decimal>)> {
+        match self {
+            Self::Adaptive(x) => {
+                let (end, average) = x.drift(
+                    rate_at_target.unwrap_or(x.initial_rate),
+                    utilization,
+                    seconds,
+                );
+                // The curve scales linearly with the rate at target, so the curve at the
+                // time-weighted average rate at target gives the average rate over the period
+                Ok((x.curve(average).rate(utilization)?, Some(end)))
+            }
+            _ => Ok((self.rate(utilization, None)?, None)),
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), ContractError> {
+        match self {
+            Self::Kink(x) => InterestRate::validate(x),
+            Self::Fixed(x) => x.validate(),
+            Self::Piecewise(x) => x.validate(),
+            Self::Adaptive(x) => x.validate(),
+        }
+    }
+}
+
+/// A gradual transition from a previous interest model to the current one. Rates are blended
+/// between the two curves in proportion to the time elapsed between `start` and `end`
+#[cw_serde]
+pub struct Ramp {
+    pub from: InterestModel,
+    pub start: Timestamp,
+    pub end: Timestamp,
+}
+
+impl Ramp {
+    /// How far through the ramp `time` is, from 0 at the start to 1 at the end
+    pub fn progress(&self, time: Timestamp) -> Decimal {
+        let (start, end) = (self.start.seconds(), self.end.seconds());
+        Decimal::from_ratio(time.seconds().clamp(start, end) - start, end - start)
+    }
+
+    /// The time-weighted average progress between `from` and `to`
+    pub fn average_progress(&self, from: Timestamp, to: Timestamp) -> Decimal {
+        let (from, to) = (from.seconds(), to.seconds());
+        if to <= from {
+            return self.progress(Timestamp::from_seconds(to));
+        }
+        let (start, end) = (self.start.seconds(), self.end.seconds());
+        let (a, b) = (from.clamp(start, end), to.clamp(start, end));
+        // Progress rises linearly while ramping, and holds at 1 after the end
+        let ramping = u128::from(a - start + b - start) * u128::from(b - a);
+        let ended = u128::from(to - from.max(end).min(to)) * u128::from(2 * (end - start));
+        Decimal::from_ratio(
+            ramping + ended,
+            u128::from(2 * (end - start)) * u128::from(to - from),
+        )
+    }
+
+    pub fn blend(&self, from: Decimal, to: Decimal, progress: Decimal) -> Decimal {
+        from * (Decimal::one() - progress) + to * progress
+    }
+}
+
+impl InterestRate for Interest {
+    fn rate(&self, utilization: Decimal) -> StdResult<Decimal> {
+        Interest::rate(self, utilization)
+    }
+
+    fn validate(&self) -> Result<(), ContractError> {
+        Interest::validate(self)?;
+        // The curve peaks at full utilization
+        if Interest::rate(self, Decimal::one())? > Decimal::percent(MAX_RATE) {
+            return Err(ContractError::Invalid("interest.step2".to_string()));
+        }
+        Ok(())
+    }
+}
+
+#[cw_serde]
+pub struct Fixed {
+    pub rate: Decimal,
+}
+
+impl InterestRate for Fixed {
+    fn rate(&self, _utilization: Decimal) -> StdResult<Decimal> {
+        Ok(self.rate)
+    }
+
+    fn validate(&self) -> Result<(), ContractError> {
+        if self.rate > Decimal::percent(MAX_RATE) {
+            return Err(ContractError::Invalid("interest.rate".to_string()));
+        }
+        Ok(())
+    }
+}
+
+#[cw_serde]
+pub struct Point {
+    pub utilization: Decimal,
+    pub rate: Decimal,
+}
+
+/// Rates are interpolated between neighbouring points, and held flat before the first point
+/// and after the last
+#[cw_serde]
+pub struct Piecewise {
+    pub points: Vec<Point>,
+}
+
+impl InterestRate for Piecewise {
+    fn rate(&self, utilization: Decimal) -> StdResult<Decimal> {
+        let upper = self
+            .points
+            .iter()
+            .position(|p| p.utilization > utilization)
+            .unwrap_or(self.points.len());
+        let (lo, hi) = match upper {
+            0 => return Ok(self.points[0].rate),
+            i if i == self.points.len() => return Ok(self.points[i - 1].rate),
+            i => (&self.points[i - 1], &self.points[i]),
+        };
+        let (offset, width) = (
+            utilization - lo.utilization,
+            hi.utilization - lo.utilization,
+        );
+        if hi.rate >= lo.rate {
+            Ok(lo.rate + (hi.rate - lo.rate) * offset / width)
+        } else {
+            Ok(lo.rate - (lo.rate - hi.rate) * offset / width)
+        }
+    }
+
+    fn validate(&self) -> Result<(), ContractError> {
+        if self.points.is_empty()
+            || self
+                .points
+                .last()
+                .is_some_and(|p| p.utilization > Decimal::one())
+            || self
+                .points
+                .windows(2)
+                .any(|w| w[0].utilization >= w[1].utilization)
+            || self
+                .points
+                .iter()
+                .any(|p| p.rate > Decimal::percent(MAX_RATE))
+        {
+            return Err(ContractError::Invalid("interest.points".to_string()));
+        }
+        Ok(())
+    }
+}
+
+/// A kink curve defined by its rate at target utilization. The rate at 0% utilization is
+/// `steepness` times lower, and at 100% `steepness` times higher.
+/// The rate at target moves continuously towards whatever rate brings utilization back to
+/// target, in proportion to the deviation from target. At 0% or 100% utilization it moves by
+/// `max_speed` of its value per year. It is held within `min_rate` and `max_rate`
+#[cw_serde]
+pub struct Adaptive {
+    pub target_utilization: Decimal,
+    /// The rate at target utilization when the model is first applied
+    pub initial_rate: Decimal,
+    pub min_rate: Decimal,
+    pub max_rate: Decimal,
+    pub steepness: Decimal,
+    pub max_speed: Decimal,
+}
+
+impl Adaptive {
+    pub fn curve(&self, rate_at_target: Decimal) -> Interest {
+        let base_rate = rate_at_target / self.steepness;
+        Interest {
+            target_utilization: self.target_utilization,
+            base_rate,
+            step1: rate_at_target - base_rate,
+            step2: rate_at_target * self.steepness - rate_at_target,
+        }
+    }
+
+    /// Moves the rate at target over a period of `seconds` held at `utilization`. Returns the
+    /// rate at the end of the period, and its time-weighted average over the period
+    pub fn drift(
+        &self,
+        rate_at_target: Decimal,
+        utilization: Decimal,
+        seconds: u64,
+    ) -> (Decimal, Decimal) {
+        // Deviation from target, scaled to 1 at 0% and 100% utilization
+        let (deviation, above) = if utilization > self.target_utilization {
+            (
+                (utilization - self.target_utilization)
+                    / (Decimal::one() - self.target_utilization),
+                true,
+            )
+        } else {
+            (
+                (self.target_utilization - utilization) / self.target_utilization,
+                false,
+            )
+        };
+        let change =
+            rate_at_target * self.max_speed * deviation * Decimal::from_ratio(seconds, YEAR);
+        let end = if above {
+            rate_at_target.saturating_add(change)
+        } else {
+            rate_at_target.saturating_sub(change)
+        };
+        let end = min(max(end, self.min_rate), self.max_rate);
+
+        // The rate moves linearly until it reaches a bound, and holds there for the rest
+        // of the period
+        let moved = end.abs_diff(rate_at_target);
+        let moving = if change.is_zero() {
+            Decimal::one()
+        } else {
+            min(moved / change, Decimal::one())
+        };
+        let offset = moved * moving * Decimal::percent(50);
+        let average = if end >= rate_at_target {
+            end - offset
+        } else {
+            end + offset
+        };
+        (end, average)
+    }
+}
+
+// The adaptive rate depends on the rate at target held in `State`, so unlike the other models
+// it isn't priced through `InterestRate`
+impl Adaptive {
+    pub fn validate(&self) -> Result<(), ContractError> {
+        if self.target_utilization.is_zero() || self.target_utilization >= Decimal::one() {
+            return Err(ContractError::Invalid(
+                "interest.target_utilization".to_string(),
+            ));
+        }
+        // The rate at target moves in proportion to itself, so it could never recover from zero
+        if self.min_rate.is_zero() {
+            return Err(ContractError::Invalid("interest.min_rate".to_string()));
+        }
+        if self.min_rate > self.initial_rate || self.initial_rate > self.max_rate {
+            return Err(ContractError::Invalid("interest.initial_rate".to_string()));
+        }
+        // Upper bounds keep the curve and the drift well clear of overflow
+        if self.max_rate > Decimal::percent(MAX_RATE) {
+            return Err(ContractError::Invalid("interest.max_rate".to_string()));
+        }
+        if self.steepness < Decimal::one() || self.steepness > Decimal::percent(MAX_STEEPNESS) {
+            return Err(ContractError::Invalid("interest.steepness".to_string()));
+        }
+        if self.max_speed > Decimal::percent(MAX_SPEED) {
+            return Err(ContractError::Invalid("interest.max_speed".to_string()));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn piecewise() {
+        let model = Piecewise {
+            points: vec![
+                Point {
+                    utilization: Decimal::percent(20),
+                    rate: Decimal::percent(2),
+                },
+                Point {
+                    utilization: Decimal::percent(60),
+                    rate: Decimal::percent(10),
+                },
+                Point {
+                    utilization: Decimal::percent(90),
+                    rate: Decimal::percent(4),
+                },
+            ],
+        };
+        model.validate().unwrap();
+
+        assert_eq!(model.rate(Decimal::zero()).unwrap(), Decimal::percent(2));
+        assert_eq!(
+            model.rate(Decimal::percent(40)).unwrap(),
+            Decimal::percent(6)
+        );
+        assert_eq!(
+            model.rate(Decimal::percent(60)).unwrap(),
+            Decimal::percent(10)
+        );
+        assert_eq!(
+            model.rate(Decimal::percent(80)).unwrap(),
+            Decimal::percent(6)
+        );
+        assert_eq!(model.rate(Decimal::one()).unwrap(), Decimal::percent(4));
+
+        Piecewise {
+            points: vec![model.points[1].clone(), model.points[0].clone()],
+        }
+        .validate()
+        .unwrap_err();
+        Piecewise { points: vec![] }.validate().unwrap_err();
+    }
+
+    #[test]
+    fn rate_bounds() {
+        let max = Decimal::percent(MAX_RATE);
+        Fixed { rate: max }.validate().unwrap();
+        Fixed {
+            rate: max + Decimal::percent(1),
+        }
+        .validate()
+        .unwrap_err();
+        Piecewise {
+            points: vec![Point {
+                utilization: Decimal::one(),
+                rate: max + Decimal::percent(1),
+            }],
+        }
+        .validate()
+        .unwrap_err();
+        let kink = Interest {
+            target_utilization: Decimal::percent(80),
+            base_rate: Decimal::percent(1),
+            step1: Decimal::percent(9),
+            step2: Decimal::percent(990),
+        };
+        InterestRate::validate(&kink).unwrap();
+        InterestRate::validate(&Interest {
+            step2: Decimal::percent(991),
+            ..kink
+        })
+        .unwrap_err();
+    }
+
+    #[test]
+    fn adaptive() {
+        let model = Adaptive {
+            target_utilization: Decimal::percent(80),
+            initial_rate: Decimal::percent(4),
+            min_rate: Decimal::percent(1),
+            max_rate: Decimal::percent(40),
+            steepness: Decimal::percent(400),
+            max_speed: Decimal::percent(5000),
+        };
+        model.validate().unwrap();
+        for invalid in [
+            Adaptive {
+                min_rate: Decimal::zero(),
+                ..model.clone()
+            },
+            Adaptive {
+                max_rate: Decimal::percent(1_001),
+                ..model.clone()
+            },
+            Adaptive {
+                steepness: Decimal::percent(10_001),
+                ..model.clone()
+            },
+            Adaptive {
+                max_speed: Decimal::percent(100_001),
+                ..model.clone()
+            },
+        ] {
+            invalid.validate().unwrap_err();
+        }
+
+        // Matches the kink curve around the rate at target
+        let curve = model.curve(model.initial_rate);
+        assert_eq!(curve.rate(Decimal::zero()).unwrap(), Decimal::percent(1));
+        assert_eq!(
+            curve.rate(Decimal::percent(80)).unwrap(),
+            Decimal::percent(4)
+        );
+        assert_eq!(curve.rate(Decimal::one()).unwrap(), Decimal::percent(16));
+
+        // A day at 90% utilization raises the rate at target by 4% * 50 * 0.5 / 365
+        let (end, average) = model.drift(Decimal::percent(4), Decimal::percent(90), 86_400);
+        assert_eq!(
+            end,
+            Decimal::percent(4) + Decimal::from_ratio(1u128, 365u128)
+        );
+        assert_eq!(
+            average,
+            Decimal::percent(4) + Decimal::from_ratio(1u128, 730u128)
+        );
+        // Idle at target, it holds
+        assert_eq!(
+            model.drift(Decimal::percent(4), Decimal::percent(80), 86_400),
+            (Decimal::percent(4), Decimal::percent(4))
+        );
+        // It is bounded either way, with the average accounting for the time spent at the bound
+        assert_eq!(
+            model.drift(Decimal::percent(4), Decimal::zero(), YEAR),
+            (
+                Decimal::percent(1),
+                Decimal::from_ratio(10_225u128, 1_000_000u128)
+            )
+        );
+        let (end, average) = model.drift(Decimal::percent(4), Decimal::one(), YEAR);
+        assert_eq!(end, Decimal::percent(40));
+        assert_eq!(average, Decimal::from_ratio(36_760u128, 100_000u128));
+
+        // Interest is charged on the curve at the average rate at target
+        let (rate, end) = InterestModel::Adaptive(model)
+            .accrue(None, Decimal::percent(90), 86_400)
+            .unwrap();
+        assert_eq!(
+            end,
+            Some(Decimal::percent(4) + Decimal::from_ratio(1u128, 365u128))
+        );
+        assert_eq!(
+            rate,
+            (Decimal::percent(4) + Decimal::from_ratio(1u128, 730u128)) * Decimal::percent(250)
+        );
+    }
+
+    #[test]
+    fn ramp() {
+        let ramp = Ramp {
+            from: InterestModel::Fixed(Fixed {
+                rate: Decimal::percent(10),
+            }),
+            start: Timestamp::from_seconds(1_000),
+            end: Timestamp::from_seconds(2_000),
+        };
+
+        assert_eq!(
+            ramp.progress(Timestamp::from_seconds(1_000)),
+            Decimal::zero()
+        );
+        assert_eq!(
+            ramp.progress(Timestamp::from_seconds(1_250)),
+            Decimal::percent(25)
+        );
+        assert_eq!(
+            ramp.progress(Timestamp::from_seconds(3_000)),
+            Decimal::one()
+        );
+
+        // Within the ramp, the average is the midpoint
+        assert_eq!(
+            ramp.average_progress(
+                Timestamp::from_seconds(1_000),
+                Timestamp::from_seconds(1_500)
+            ),
+            Decimal::percent(25)
+        );
+        // Half the period ramping from 50% to 100%, half at 100%
+        assert_eq!(
+            ramp.average_progress(
+                Timestamp::from_seconds(1_500),
+                Timestamp::from_seconds(2_500)
+            ),
+            Decimal::permille(875)
+        );
+        assert_eq!(
+            ramp.average_progress(
+                Timestamp::from_seconds(2_500),
+                Timestamp::from_seconds(3_000)
+            ),
+            Decimal::one()
+        );
+
+        assert_eq!(
+            ramp.blend(
+                Decimal::percent(10),
+                Decimal::percent(50),
+                Decimal::percent(25)
+            ),
+            Decimal::percent(20)
+        );
+    }
+
+    #[test]
+    fn compounding() {
+        assert_eq!(compound(Decimal256::zero()).unwrap(), Decimal256::zero());
+        // e^0.1 - 1 and e - 1, truncation costing a few units in the last digit
+        assert_eq!(
+            compound(Decimal256::percent(10)).unwrap().to_string(),
+            "0.105170918075647619"
+        );
+        assert_eq!(
+            compound(Decimal256::one()).unwrap().to_string(),
+            "1.718281828459045226"
+        );
+        // Growth beyond what a decimal can hold is an error, not a panic
+        compound(Decimal256::from_ratio(200u128, 1u128)).unwrap_err();
+    }
+}
@@ -3,6 +3,10 @@ pub mod config;
 pub mod contract;
 mod error;
 mod events;
+mod flash;
+pub mod interest;
+pub mod msg;
+mod queue;
 mod state;
 
 pub use crate::error::ContractError;
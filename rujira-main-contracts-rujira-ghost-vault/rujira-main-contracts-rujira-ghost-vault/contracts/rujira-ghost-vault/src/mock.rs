@@ -1,12 +1,16 @@
-use cosmwasm_std::{coins, Addr, Decimal, Uint128};
+use cosmwasm_schema::cw_serde;
+use cosmwasm_std::{
+    coins, to_json_binary, Addr, BankMsg, Binary, Decimal, Deps, DepsMut, Empty, Env, MessageInfo,
+    Response, StdResult, Uint128, WasmMsg,
+};
 use cw_multi_test::{ContractWrapper, Executor};
+use cw_storage_plus::Item;
 
-use rujira_rs::{
-    ghost::{self, vault::Interest},
-    TokenMetadata,
-};
+use rujira_rs::{ghost::vault::Interest, CallbackData, CallbackMsg, TokenMetadata};
 use rujira_rs_testing::RujiraApp;
 
+use crate::{interest::InterestModel, msg};
+
 /// Wrapper struct for Ghost Vault contract with convenience methods
 #[derive(Debug, Clone)]
 pub struct GhostVault(pub Addr);
@@ -28,7 +32,11 @@ impl GhostVault {
         app.execute_contract(
             sender.clone(),
             self.0.clone(),
-            &ghost::vault::ExecuteMsg::Deposit { callback: None },
+            &msg::ExecuteMsg::Deposit {
+                callback: None,
+                min_shares: None,
+                deadline: None,
+            },
             &coins(amount, denom),
         )
     }
@@ -43,7 +51,12 @@ impl GhostVault {
         app.execute_contract(
             sender.clone(),
             self.0.clone(),
-            &ghost::vault::ExecuteMsg::Withdraw { callback: None },
+            &msg::ExecuteMsg::Withdraw {
+                callback: None,
+                amount: None,
+                min_amount: None,
+                deadline: None,
+            },
             &coins(amount.u128(), "receipt-token"), // Withdraw by burning receipt tokens
         )
     }
@@ -57,7 +70,7 @@ impl GhostVault {
     ) -> anyhow::Result<cw_multi_test::AppResponse> {
         app.wasm_sudo(
             self.0.clone(),
-            &ghost::vault::SudoMsg::SetBorrower {
+            &msg::SudoMsg::SetBorrower {
                 contract: contract.to_string(),
                 limit,
             },
@@ -65,10 +78,10 @@ impl GhostVault {
     }
 
     /// Query vault status
-    pub fn query_status(&self, app: &RujiraApp) -> anyhow::Result<ghost::vault::StatusResponse> {
+    pub fn query_status(&self, app: &RujiraApp) -> anyhow::Result<msg::StatusResponse> {
         Ok(app
             .wrap()
-            .query_wasm_smart(self.0.clone(), &ghost::vault::QueryMsg::Status {})?)
+            .query_wasm_smart(self.0.clone(), &msg::QueryMsg::Status {})?)
     }
 
     /// Query borrower info
@@ -76,10 +89,10 @@ impl GhostVault {
         &self,
         app: &RujiraApp,
         addr: &str,
-    ) -> anyhow::Result<ghost::vault::BorrowerResponse> {
+    ) -> anyhow::Result<msg::BorrowerResponse> {
         Ok(app.wrap().query_wasm_smart(
             self.0.clone(),
-            &ghost::vault::QueryMsg::Borrower {
+            &msg::QueryMsg::Borrower {
                 addr: addr.to_string(),
             },
         )?)
@@ -93,7 +106,8 @@ impl GhostVault {
                 crate::contract::instantiate,
                 crate::contract::query,
             )
-            .with_sudo(crate::contract::sudo),
+            .with_sudo(crate::contract::sudo)
+            .with_reply(crate::contract::reply),
         );
         let vault_code_id = app.store_code(vault_code);
 
@@ -101,9 +115,9 @@ impl GhostVault {
             .instantiate_contract(
                 vault_code_id,
                 owner.clone(),
-                &ghost::vault::InstantiateMsg {
+                &msg::InstantiateMsg {
                     denom: denom.to_string(),
-                    interest: Interest::default(),
+                    interest: InterestModel::Kink(Interest::default()),
                     receipt: TokenMetadata {
                         description: denom.to_string(),
                         display: denom.to_string(),
@@ -124,6 +138,113 @@ impl GhostVault {
         Self(vault_addr)
     }
 }
+
+/// A flash loan receiver that returns each loan to the vault with a premium on top
+#[derive(Debug, Clone)]
+pub struct FlashReceiver(pub Addr);
+
+#[cw_serde]
+pub enum FlashReceiverMsg {
+    /// Take a flash loan of `amount` from `vault`, repaying `premium` on top of it
+    Borrow {
+        vault: String,
+        amount: Uint128,
+        premium: Uint128,
+    },
+    Callback(CallbackMsg),
+}
+
+static PREMIUM: Item<Uint128> = Item::new("premium");
+
+impl FlashReceiver {
+    /// Execute a flash loan through the receiver. The receiver must hold enough of the
+    /// borrowable asset to pay the premium
+    pub fn flash_loan(
+        &self,
+        app: &mut RujiraApp,
+        sender: &Addr,
+        vault: &GhostVault,
+        amount: Uint128,
+        premium: Uint128,
+    ) -> anyhow::Result<cw_multi_test::AppResponse> {
+        app.execute_contract(
+            sender.clone(),
+            self.0.clone(),
+            &FlashReceiverMsg::Borrow {
+                vault: vault.addr().to_string(),
+                amount,
+                premium,
+            },
+            &[],
+        )
+    }
+
+    pub fn create(app: &mut RujiraApp, owner: &Addr) -> Self {
+        let code = Box::new(ContractWrapper::new(
+            flash_receiver_execute,
+            flash_receiver_instantiate,
+            flash_receiver_query,
+        ));
+        let code_id = app.store_code(code);
+        let addr = app
+            .instantiate_contract(
+                code_id,
+                owner.clone(),
+                &Empty {},
+                &[],
+                "flash-receiver",
+                None,
+            )
+            .unwrap();
+        Self(addr)
+    }
+}
+
+fn flash_receiver_instantiate(
+    _deps: DepsMut,
+    _env: Env,
+    _info: MessageInfo,
+    _msg: Empty,
+) -> StdResult<Response> {
+    Ok(Response::default())
+}
+
+fn flash_receiver_execute(
+    deps: DepsMut,
+    _env: Env,
+    info: MessageInfo,
+    msg: FlashReceiverMsg,
+) -> StdResult<Response> {
+    match msg {
+        FlashReceiverMsg::Borrow {
+            vault,
+            amount,
+            premium,
+        } => {
+            PREMIUM.save(deps.storage, &premium)?;
+            Ok(Response::default().add_message(WasmMsg::Execute {
+                contract_addr: vault,
+                msg: to_json_binary(&msg::ExecuteMsg::FlashLoan {
+                    amount,
+                    callback: CallbackData(Binary::default()),
+                })?,
+                funds: vec![],
+            }))
+        }
+        FlashReceiverMsg::Callback(_) => {
+            let premium = PREMIUM.load(deps.storage)?;
+            let loan = &info.funds[0];
+            Ok(Response::default().add_message(BankMsg::Send {
+                to_address: info.sender.to_string(),
+                amount: coins(loan.amount.u128() + premium.u128(), &loan.denom),
+            }))
+        }
+    }
+}
+
+fn flash_receiver_query(_deps: Deps, _env: Env, _msg: Empty) -> StdResult<Binary> {
+    Ok(Binary::default())
+}
 #[cfg(test)]
 mod tests {
     use cosmwasm_std::coin;
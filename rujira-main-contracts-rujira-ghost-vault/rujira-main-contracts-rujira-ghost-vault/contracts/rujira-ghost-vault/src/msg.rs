@@ -0,0 +1,517 @@
+use cosmwasm_schema::{cw_serde, QueryResponses};
+use cosmwasm_std::{Decimal, Timestamp, Uint128};
+use rujira_rs::{CallbackData, TokenMetadata};
+
+use crate::{
+    borrowers::{BorrowerStatus, DepositLimit},
+    interest::InterestModel,
+};
+
+#[cw_serde]
+pub struct InstantiateMsg {
+    /// The denom string that can be deposited and lent
+    pub denom: String,
+    pub receipt: TokenMetadata,
+    pub interest: InterestModel,
+    pub fee: Decimal,
+    pub fee_address: String,
+}
+
+#[cw_serde]
+pub enum ExecuteMsg {
+    /// Deposit the borrowable asset into the money market.
+    Deposit {
+        callback: Option<CallbackData>,
+        /// Fail unless at least this many receipt tokens are minted
+        min_shares: Option<Uint128>,
+        /// Fail if executed after this time
+        deadline: Option<Timestamp>,
+    },
+    /// Withdraw the borrowable asset from the money market.
+    Withdraw {
+        callback: Option<CallbackData>,
+        /// Withdraw exactly this much of the borrowable asset. The receipt tokens sent are a
+        /// maximum, only the shares needed are burnt and the remainder is refunded
+        amount: Option<Uint128>,
+        /// Fail unless at least this much of the borrowable asset is returned
+        min_amount: Option<Uint128>,
+        /// Fail if executed after this time
+        deadline: Option<Timestamp>,
+    },
+    /// Escrow receipt tokens in the withdrawal queue, for when the vault lacks the liquidity to
+    /// withdraw them immediately. Tickets are filled in order as deposits and repays return
+    /// liquidity to the vault
+    QueueWithdraw {},
+    /// Pay out a filled withdrawal ticket
+    ClaimWithdraw { id: u64 },
+    /// Cancel a withdrawal ticket that hasn't been filled, returning the escrowed receipt tokens
+    CancelWithdraw { id: u64 },
+    /// Repay a borrower's debt, or one of its delegate's, on its behalf. Any overpayment is
+    /// refunded to the sender
+    RepayFor {
+        borrower: String,
+        delegate: Option<String>,
+    },
+    /// Borrow idle liquidity for the duration of a callback to the sender. The loan plus the
+    /// flash fee must be returned to the vault by the time the callback completes
+    FlashLoan {
+        amount: Uint128,
+        callback: CallbackData,
+    },
+    /// Accrue interest up to the current block and mint the protocol fee
+    Accrue {},
+    /// Deposit the borrowable asset into the vault's reserve. No receipt tokens are issued, the
+    /// deposit covers future bad debt ahead of depositors. Restricted to the fee address and
+    /// the guardian, as governance can't attach funds to a sudo call
+    FundReserve {},
+    /// Privileged Msgs for whitelisted contracts
+    Market(MarketMsg),
+    /// Privileged Msgs for the guardian address
+    Guardian(GuardianMsg),
+}
+
+#[cw_serde]
+pub enum MarketMsg {
+    /// Borrow the borrowable asset from the money market. Only callable by whitelisted market contracts.
+    Borrow {
+        amount: Uint128,
+        callback: Option<CallbackData>,
+        /// optional delegate address for the debt obligation to be allocated to
+        delegate: Option<String>,
+    },
+    /// Repay a borrow. Only callable by whitelisted market contracts. Without a delegate, the
+    /// debt borrowed without one is repaid first, then every delegate's in proportion
+    Repay {
+        /// Optionally repay a delegate's debt obligation instead of the caller's
+        delegate: Option<String>,
+    },
+    /// Cap the debt of one of the caller's delegates. `None` removes the cap
+    SetDelegateLimit {
+        delegate: String,
+        limit: Option<Uint128>,
+    },
+    /// Move debt shares between the caller's own obligations, `None` being the debt borrowed
+    /// without a delegate. The destination delegate's cap applies
+    TransferDebt {
+        from_delegate: Option<String>,
+        to_delegate: Option<String>,
+        shares: Uint128,
+    },
+    /// Borrow exactly a number of debt shares, for their value rounded down
+    BorrowShares {
+        shares: Uint128,
+        callback: Option<CallbackData>,
+        delegate: Option<String>,
+    },
+    /// Repay a number of debt shares, capped at the obligation. The funds sent must cover their
+    /// value rounded up, any surplus is refunded
+    RepayShares {
+        shares: Uint128,
+        delegate: Option<String>,
+    },
+}
+
+#[cw_serde]
+pub enum GuardianMsg {
+    /// Block the flagged operations. The guardian can only add restrictions,
+    /// lifting them requires governance
+    Pause(Pause),
+    /// Block deposits and borrows, leaving withdrawals and repays open
+    Shutdown {},
+}
+
+/// Operations that are currently blocked
+#[cw_serde]
+#[derive(Default)]
+pub struct Pause {
+    pub deposit: bool,
+    pub withdraw: bool,
+    pub borrow: bool,
+    pub repay: bool,
+    #[serde(default)]
+    pub flash_loan: bool,
+}
+
+impl Pause {
+    /// Stops new exposure to the vault whilst depositors can still exit and
+    /// borrowers can still close their debt
+    pub fn shutdown() -> Self {
+        Self {
+            deposit: true,
+            withdraw: false,
+            borrow: true,
+            repay: false,
+            flash_loan: true,
+        }
+    }
+
+    pub fn merge(&self, other: &Self) -> Self {
+        Self {
+            deposit: self.deposit || other.deposit,
+            withdraw: self.withdraw || other.withdraw,
+            borrow: self.borrow || other.borrow,
+            repay: self.repay || other.repay,
+            flash_loan: self.flash_loan || other.flash_loan,
+        }
+    }
+}
+
+#[cw_serde]
+pub enum SudoMsg {
+    SetBorrower {
+        contract: String,
+        limit: Uint128,
+    },
+    /// Scale an existing borrower's limit with the vault's deposits. `None` restores the
+    /// absolute limit
+    SetBorrowerDepositLimit {
+        contract: String,
+        limit: Option<DepositLimit>,
+    },
+    /// Replace the interest rate model, cancelling any ramp in progress.
+    /// Interest accrued up to this block is settled under the previous model
+    SetInterest(InterestModel),
+    /// Move to a new interest rate model gradually, blending rates between the current curve
+    /// and the new one over `duration` seconds
+    RampInterest {
+        interest: InterestModel,
+        duration: u64,
+    },
+    /// Update the share of interest charged as a protocol fee.
+    /// Interest accrued up to this block is settled at the previous fee
+    SetFee(Decimal),
+    /// Update the recipient of protocol fees.
+    /// Fees accrued up to this block are minted to the previous address
+    SetFeeAddress(String),
+    /// Update the bank metadata of the receipt token
+    SetReceiptMetadata(TokenMetadata),
+    /// Overwrite the set of blocked operations
+    SetPause(Pause),
+    /// Set or clear the address allowed to pause the vault
+    SetGuardian(Option<String>),
+    /// Limit deposits, either across the whole vault or by the underlying value
+    /// of a single address's receipt tokens. `None` removes the cap
+    SetDepositCap {
+        total: Option<Uint128>,
+        depositor: Option<Uint128>,
+    },
+    /// Set the utilization above which borrows are rejected
+    SetMaxUtilization(Decimal),
+    /// Set the fee charged on flash loans, as a fraction of the amount borrowed
+    SetFlashFee(Decimal),
+    /// Limit the size of a single flash loan. `None` removes the limit
+    SetFlashLimit(Option<Uint128>),
+    /// Write off debt that a borrower won't repay. The loss is taken from the deposit pool,
+    /// lowering the value of every receipt token
+    WriteOff {
+        borrower: String,
+        /// Write off a delegate's debt obligation instead of the borrower's own
+        delegate: Option<String>,
+        /// The debt shares to write off, defaulting to all of them. Without a delegate, a partial
+        /// write-off only reaches the borrower's undelegated debt
+        shares: Option<Uint128>,
+    },
+    /// Charge a borrower a spread on top of the pool's debt rate, credited to depositors.
+    /// The spread accrued up to this block is charged at the previous rate
+    SetBorrowerSpread {
+        contract: String,
+        spread: Decimal,
+    },
+    /// Offboard a borrower. Only succeeds once it holds no debt
+    RemoveBorrower {
+        contract: String,
+    },
+    /// Move a borrower into or out of wind-down. Premium accrued up to this block is charged
+    /// at the previous status
+    SetBorrowerStatus {
+        contract: String,
+        status: BorrowerStatus,
+    },
+    /// Move all of a borrower's debt, and its delegates' obligations, onto another whitelisted
+    /// borrower within that borrower's limit
+    MigrateBorrowerDebt {
+        from: String,
+        to: String,
+    },
+    /// Switch between simple and continuously compounded interest.
+    /// Interest accrued up to this block is settled under the current setting
+    SetCompounding(bool),
+    /// Set the share of protocol fees retained in the reserve.
+    /// Fees accrued up to this block are split at the previous fraction
+    SetReserveFraction(Decimal),
+    /// Mint receipt tokens for reserve shares, defaulting to the whole reserve, to an address
+    SweepReserve {
+        shares: Option<Uint128>,
+        to: String,
+    },
+}
+
+#[cw_serde]
+#[derive(QueryResponses)]
+pub enum QueryMsg {
+    #[returns(ConfigResponse)]
+    Config {},
+    /// The vault's fees, caps, pauses and other settings beyond its denom and interest model
+    #[returns(SettingsResponse)]
+    Settings {},
+    #[returns(StatusResponse)]
+    Status {},
+    /// The state of the interest rate model, and any ramp to a new one
+    #[returns(InterestResponse)]
+    Interest {},
+    #[returns(BorrowerResponse)]
+    Borrower { addr: String },
+    /// A borrower's status, spread and the rate charged on its debt
+    #[returns(BorrowerTermsResponse)]
+    BorrowerTerms { addr: String },
+    #[returns(DelegateResponse)]
+    Delegate { borrower: String, addr: String },
+    /// A borrower's delegates and their debt, with totals across all delegates
+    #[returns(DelegatesResponse)]
+    Delegates {
+        borrower: String,
+        limit: Option<u8>,
+        start_after: Option<String>,
+    },
+    #[returns(BorrowersResponse)]
+    Borrowers {
+        limit: Option<u8>,
+        start_after: Option<String>,
+    },
+    /// Withdrawal tickets waiting to be filled, in the order they will be filled
+    #[returns(WithdrawalQueueResponse)]
+    WithdrawalQueue {
+        limit: Option<u8>,
+        start_after: Option<u64>,
+    },
+    #[returns(TicketResponse)]
+    WithdrawalTicket { id: u64 },
+    #[returns(ReserveResponse)]
+    Reserve {},
+    /// The receipt tokens minted by depositing `amount`
+    #[returns(PreviewResponse)]
+    PreviewDeposit { amount: Uint128 },
+    /// The underlying returned by withdrawing `shares` receipt tokens
+    #[returns(PreviewResponse)]
+    PreviewWithdraw { shares: Uint128 },
+    /// The debt shares issued to `borrower` for borrowing `amount`
+    #[returns(PreviewResponse)]
+    PreviewBorrow { borrower: String, amount: Uint128 },
+    /// The debt shares repaid, and the amount kept after any refund, when `borrower` repays `amount`
+    #[returns(PreviewResponse)]
+    PreviewRepay { borrower: String, amount: Uint128 },
+    /// The most `addr` can withdraw immediately with the receipt tokens it holds
+    #[returns(PreviewResponse)]
+    MaxWithdraw { addr: String },
+    /// The most `borrower` can borrow, within its limit and the vault's liquidity
+    #[returns(PreviewResponse)]
+    MaxBorrow { borrower: String },
+    /// The value of an address's receipt tokens
+    #[returns(PositionResponse)]
+    Position { addr: String },
+    #[returns(PositionsResponse)]
+    Positions { addrs: Vec<String> },
+}
+
+#[cw_serde]
+pub struct PreviewResponse {
+    /// The receipt tokens or debt shares minted or burnt
+    pub shares: Uint128,
+    /// The underlying deposited, withdrawn, borrowed or repaid
+    pub amount: Uint128,
+    /// The vault's utilization once the action is applied
+    pub utilization_ratio: Decimal,
+    /// The debt rate once the action is applied
+    pub debt_rate: Decimal,
+    /// The lend rate once the action is applied
+    pub lend_rate: Decimal,
+}
+
+#[cw_serde]
+pub struct PositionResponse {
+    pub addr: String,
+    /// The receipt tokens held
+    pub shares: Uint128,
+    /// The current underlying value of the receipt tokens
+    pub value: Uint128,
+    /// The fraction of the deposit pool owned
+    pub pool_share: Decimal,
+    /// The rate currently earned on the position
+    pub lend_rate: Decimal,
+}
+
+#[cw_serde]
+pub struct PositionsResponse {
+    pub positions: Vec<PositionResponse>,
+}
+
+#[cw_serde]
+pub struct ConfigResponse {
+    pub denom: String,
+    pub interest: InterestModel,
+}
+
+#[cw_serde]
+pub struct SettingsResponse {
+    pub fee: Decimal,
+    pub fee_address: String,
+    pub guardian: Option<String>,
+    pub pause: Pause,
+    /// The maximum total deposits into the vault
+    pub deposit_cap: Option<Uint128>,
+    /// The maximum underlying value of a single address's receipt tokens
+    pub depositor_cap: Option<Uint128>,
+    /// The utilization above which borrows are rejected
+    pub max_utilization: Decimal,
+    /// The share of protocol fees retained in the reserve
+    pub reserve_fraction: Decimal,
+    /// Whether interest is continuously compounded
+    pub compounding: bool,
+    /// The fee charged on flash loans
+    pub flash_fee: Decimal,
+    /// The largest flash loan allowed
+    pub flash_limit: Option<Uint128>,
+}
+
+#[cw_serde]
+pub struct StatusResponse {
+    pub last_updated: Timestamp,
+    pub utilization_ratio: Decimal,
+    pub debt_rate: Decimal,
+    pub lend_rate: Decimal,
+    pub debt_pool: PoolResponse,
+    pub deposit_pool: PoolResponse,
+}
+
+#[cw_serde]
+pub struct InterestResponse {
+    pub interest: InterestModel,
+    /// The adaptive interest model's current rate at target utilization
+    pub rate_at_target: Option<Decimal>,
+    /// Set while the interest rate model is ramping to a new curve
+    pub ramp: Option<RampResponse>,
+}
+
+#[cw_serde]
+pub struct RampResponse {
+    /// The interest rate model being ramped away from
+    pub from: InterestModel,
+    /// The debt rate on the previous curve at the current utilization
+    pub from_rate: Decimal,
+    /// The interest rate model being ramped to
+    pub to: InterestModel,
+    /// The debt rate on the new curve at the current utilization
+    pub to_rate: Decimal,
+    pub start: Timestamp,
+    pub end: Timestamp,
+    /// How far through the ramp the vault is, from 0 to 1
+    pub progress: Decimal,
+}
+
+#[cw_serde]
+pub struct PoolResponse {
+    /// The total deposits into the pool
+    pub size: Uint128,
+    /// The total ownership of the pool
+    pub shares: Uint128,
+    /// Ratio of shares / size
+    pub ratio: Decimal,
+}
+
+#[cw_serde]
+pub struct BorrowerResponse {
+    pub addr: String,
+    /// The denom being borrowed
+    pub denom: String,
+    /// The borrower's borrow limit, in absolute terms at the vault's current deposits
+    pub limit: Uint128,
+    /// The borrower's current utilization
+    pub current: Uint128,
+    /// The shares allocated to the current debt
+    pub shares: Uint128,
+    /// The remaining amount of borrowable funds for this borrower
+    pub available: Uint128,
+}
+
+#[cw_serde]
+pub struct BorrowerTermsResponse {
+    pub addr: String,
+    /// Set when the limit scales with the vault's deposits
+    pub deposit_limit: Option<DepositLimit>,
+    /// Whether the borrower is active or winding down, with any premium charged on its debt
+    pub status: BorrowerStatus,
+    /// The borrower's spread over the pool's debt rate
+    pub spread: Decimal,
+    /// The annual rate charged on the borrower's debt: the pool's debt rate, its spread and
+    /// any wind-down premium
+    pub rate: Decimal,
+}
+
+#[cw_serde]
+pub struct BorrowersResponse {
+    pub borrowers: Vec<BorrowerResponse>,
+}
+
+#[cw_serde]
+pub struct DelegateResponse {
+    pub borrower: BorrowerResponse,
+    pub addr: String,
+    /// The borrower's current utilization
+    pub current: Uint128,
+    /// The shares allocated to the current debt
+    pub shares: Uint128,
+}
+
+#[cw_serde]
+pub struct DelegatesResponse {
+    pub borrower: BorrowerResponse,
+    pub delegates: Vec<DelegateDebtResponse>,
+    /// The shares allocated across all delegates, not only those listed
+    pub delegated_shares: Uint128,
+    /// The current debt of all delegates
+    pub delegated: Uint128,
+    /// The shares borrowed without a delegate
+    pub undelegated_shares: Uint128,
+    /// The current debt borrowed without a delegate
+    pub undelegated: Uint128,
+}
+
+#[cw_serde]
+pub struct DelegateDebtResponse {
+    pub addr: String,
+    pub current: Uint128,
+    pub shares: Uint128,
+    pub limit: Option<Uint128>,
+}
+
+#[cw_serde]
+pub struct WithdrawalQueueResponse {
+    /// The receipt tokens escrowed by tickets waiting to be filled
+    pub shares: Uint128,
+    /// The current underlying value of the escrowed receipt tokens
+    pub value: Uint128,
+    pub tickets: Vec<TicketResponse>,
+}
+
+#[cw_serde]
+pub struct TicketResponse {
+    pub id: u64,
+    pub owner: String,
+    /// The receipt tokens escrowed by the ticket
+    pub shares: Uint128,
+    /// The underlying owed to the owner. Until the ticket is filled this is the current
+    /// value of the escrowed shares
+    pub amount: Uint128,
+    pub filled: bool,
+    pub created: Timestamp,
+}
+
+#[cw_serde]
+pub struct ReserveResponse {
+    /// The share of protocol fees retained in the reserve
+    pub fraction: Decimal,
+    /// The deposit shares owned by the reserve
+    pub shares: Uint128,
+    /// The current underlying value of the reserve
+    pub value: Uint128,
+}
@@ -0,0 +1,122 @@
+use crate::{state::State, ContractError};
+use cosmwasm_schema::cw_serde;
+use cosmwasm_std::{Addr, Empty, Order, StdError, StdResult, Storage, Timestamp, Uint128};
+use cw_storage_plus::{Bound, Item, Map};
+
+static TICKETS: Map<u64, Ticket> = Map::new("tickets");
+// Ids of tickets waiting to be filled, in the order they were requested
+static QUEUE: Map<u64, Empty> = Map::new("queue");
+static NEXT_TICKET: Item<u64> = Item::new("next_ticket");
+
+// Bounds the number of tickets filled in a single call
+const FILL_LIMIT: usize = 10;
+
+#[cw_serde]
+pub struct Ticket {
+    pub id: u64,
+    pub owner: Addr,
+    /// Receipt tokens escrowed by the vault until the ticket is filled or cancelled
+    pub shares: Uint128,
+    /// Underlying reserved for the owner once the ticket has been filled
+    pub amount: Option<Uint128>,
+    pub created: Timestamp,
+}
+
+impl Ticket {
+    pub fn create(
+        storage: &mut dyn Storage,
+        state: &mut State,
+        owner: Addr,
+        shares: Uint128,
+        created: Timestamp,
+    ) -> StdResult<Self> {
+        let id = NEXT_TICKET.may_load(storage)?.unwrap_or_default();
+        NEXT_TICKET.save(storage, &(id + 1))?;
+        let ticket = Self {
+            id,
+            owner,
+            shares,
+            amount: None,
+            created,
+        };
+        TICKETS.save(storage, id, &ticket)?;
+        QUEUE.save(storage, id, &Empty {})?;
+        state.queued_shares += shares;
+        Ok(ticket)
+    }
+
+    pub fn load(storage: &dyn Storage, id: u64) -> Result<Self, ContractError> {
+        match TICKETS.load(storage, id) {
+            Ok(x) => Ok(x),
+            Err(StdError::NotFound { .. }) => Err(ContractError::UnknownTicket { id }),
+            Err(err) => Err(ContractError::Std(err)),
+        }
+    }
+
+    /// Pays out a filled ticket, returning the underlying owed to its owner
+    pub fn claim(&self, storage: &mut dyn Storage) -> Result<Uint128, ContractError> {
+        let amount = self
+            .amount
+            .ok_or(ContractError::TicketPending { id: self.id })?;
+        TICKETS.remove(storage, self.id);
+        Ok(amount)
+    }
+
+    /// Removes a ticket that is still queued, returning the escrowed shares
+    pub fn cancel(
+        &self,
+        storage: &mut dyn Storage,
+        state: &mut State,
+    ) -> Result<Uint128, ContractError> {
+        if self.amount.is_some() {
+            return Err(ContractError::TicketFilled { id: self.id });
+        }
+        TICKETS.remove(storage, self.id);
+        QUEUE.remove(storage, self.id);
+        state.queued_shares = state.queued_shares.checked_sub(self.shares)?;
+        Ok(self.shares)
+    }
+
+    /// Tickets waiting to be filled, in queue order
+    pub fn queued(
+        storage: &dyn Storage,
+        limit: Option<u8>,
+        start_after: Option<u64>,
+    ) -> impl Iterator<Item = StdResult<Self>> + '_ {
+        let limit = limit.unwrap_or(100) as usize;
+        let min = start_after.map(Bound::exclusive);
+        QUEUE
+            .keys(storage, min, None, Order::Ascending)
+            .take(limit)
+            .map(|id| id.and_then(|id| TICKETS.load(storage, id)))
+    }
+}
+
+/// Fills queued tickets in order for as long as the vault has the liquidity to pay them,
+/// removing their shares from the deposit pool. Returns the tickets filled
+pub fn fill(storage: &mut dyn Storage, state: &mut State) -> Result<Vec<Ticket>, ContractError> {
+    let queued = QUEUE
+        .keys(storage, None, None, Order::Ascending)
+        .take(FILL_LIMIT)
+        .collect::<StdResult<Vec<u64>>>()?;
+    let mut filled = vec![];
+
+    for id in queued {
+        let mut ticket = TICKETS.load(storage, id)?;
+        let liquid = state
+            .deposit_pool
+            .size()
+            .checked_sub(state.debt_pool.size())
+            .unwrap_or_default();
+        if state.deposit_pool.ownership(ticket.shares).gt(&liquid) {
+            break;
+        }
+        state.queued_shares = state.queued_shares.checked_sub(ticket.shares)?;
+        ticket.amount = Some(state.deposit_pool.leave(ticket.shares)?);
+        TICKETS.save(storage, id, &ticket)?;
+        QUEUE.remove(storage, id);
+        filled.push(ticket);
+    }
+
+    Ok(filled)
+}
@@ -1,13 +1,35 @@
 use cosmwasm_schema::cw_serde;
 use cosmwasm_std::{Decimal, Decimal256, Env, StdResult, Storage, Timestamp, Uint128};
 use cw_storage_plus::Item;
-use rujira_rs::{ghost::vault::Interest, DecimalScaled, SharePool, SharePoolError};
-use std::ops::{Add, Mul, Sub};
-
-use crate::{config::Config, ContractError};
+use rujira_rs::{DecimalScaled, SharePool, SharePoolError};
+use std::{
+    cmp::min,
+    ops::{Add, Mul, Sub},
+};
+
+use crate::{
+    config::Config,
+    interest::{compound, InterestModel, Ramp, YEAR},
+    ContractError,
+};
 
 static STATE: Item<State> = Item::new("state");
 
+/// The result of accruing interest since the last update
+pub struct Accrual {
+    pub seconds: u64,
+    /// The debt rate charged over the period
+    pub rate: Decimal,
+    /// The interest allocated to depositors
+    pub interest: Uint128,
+    /// The protocol fee charged on top
+    pub fee: Uint128,
+    /// The fee shares to mint to the fee address, net of the reserve's share
+    pub shares: Uint128,
+    /// Borrower premiums charged to the debt pool and credited to depositors
+    pub premium: Uint128,
+}
+
 #[cw_serde]
 pub struct State {
     pub last_updated: Timestamp,
@@ -23,6 +45,28 @@ pub struct State {
     pub pending_interest: DecimalScaled,
     #[serde(default)]
     pub pending_fees: DecimalScaled,
+    // Receipt tokens escrowed in the withdrawal queue. These remain in the deposit_pool,
+    // earning interest, until their ticket is filled
+    #[serde(default)]
+    pub queued_shares: Uint128,
+    // Deposit shares owned by the vault itself, taken from protocol fees and funded by
+    // governance. These take the first loss when debt is written off
+    #[serde(default)]
+    pub reserve_shares: Uint128,
+    // The adaptive interest model's current rate at target utilization
+    #[serde(default)]
+    pub rate_at_target: Option<Decimal>,
+    // Debt shares charged per year in borrower premiums, the sum of each borrower's debt
+    // shares times its premium. Premiums are charged to the debt pool and credited to
+    // depositors at this rate, and allocated to each borrower when its debt next changes
+    #[serde(default)]
+    pub premium_rate: Decimal256,
+    // Premium debt shares accrued and not yet allocated to a borrower
+    #[serde(default)]
+    pub premium_accrued: Decimal256,
+    // Debt shares issued for premiums and not yet allocated to a borrower
+    #[serde(default)]
+    pub premium_shares: Uint128,
 }
 
 impl State {
@@ -35,6 +79,12 @@ impl State {
                 deposit_pool: SharePool::default(),
                 pending_interest: DecimalScaled::zero(),
                 pending_fees: DecimalScaled::zero(),
+                queued_shares: Uint128::zero(),
+                reserve_shares: Uint128::zero(),
+                rate_at_target: None,
+                premium_rate: Decimal256::zero(),
+                premium_accrued: Decimal256::zero(),
+                premium_shares: Uint128::zero(),
             },
         )?;
 
@@ -54,14 +104,99 @@ impl State {
     }//*called by execute() in contract.rs
 
     pub fn withdraw(&mut self, amount: Uint128) -> Result<Uint128, ContractError> {
+        let available = self.liquidity();
         let withdrawn = self.deposit_pool.leave(amount)?;
+        if withdrawn.gt(&available) {
+            return Err(ContractError::InsufficientLiquidity {
+                available,
+                requested: withdrawn,
+            });
+        }
         Ok(withdrawn)
     }//*called by execute()
 
-    pub fn borrow(&mut self, amount: Uint128) -> Result<Uint128, ContractError> {
+    /// Withdraws at least `amount` using at most `budget` shares. The shares burnt are rounded
+    /// up in favour of the vault. Returns the shares burnt and the amount withdrawn
+    pub fn withdraw_exact(
+        &mut self,
+        amount: Uint128,
+        budget: Uint128,
+    ) -> Result<(Uint128, Uint128), ContractError> {
+        let shares =
+            amount.checked_mul_ceil((self.deposit_pool.shares(), self.deposit_pool.size()))?;
+        if shares.gt(&budget) {
+            return Err(ContractError::InsufficientShares {
+                required: shares,
+                sent: budget,
+            });
+        }
+        Ok((shares, self.withdraw(shares)?))
+    }
+
+    pub fn borrow(
+        &mut self,
+        amount: Uint128,
+        max_utilization: Decimal,
+    ) -> Result<Uint128, ContractError> {
+        let available = self.borrowable(max_utilization);
+        if amount.gt(&available) {
+            return Err(ContractError::InsufficientLiquidity {
+                available,
+                requested: amount,
+            });
+        }
         Ok(self.debt_pool.join(amount)?)
     }//*caled by execute()
 
+    /// Issues exactly `shares` of debt for their value rounded down, returning the amount
+    /// borrowed
+    pub fn borrow_shares(
+        &mut self,
+        shares: Uint128,
+        max_utilization: Decimal,
+    ) -> Result<Uint128, ContractError> {
+        let amount = self.borrow_value(shares);
+        let available = self.borrowable(max_utilization);
+        if amount.gt(&available) {
+            return Err(ContractError::InsufficientLiquidity {
+                available,
+                requested: amount,
+            });
+        }
+        // Joining with the value rounded up issues the shares, the rounding is then taken back
+        // out of the pool
+        let cost = self.repay_value(shares)?;
+        let issued = self.debt_pool.join(cost)?;
+        self.debt_pool.withdraw(cost.sub(amount))?;
+        if issued.ne(&shares) {
+            return Err(ContractError::Invalid("shares".to_string()));
+        }
+        Ok(amount)
+    }
+
+    /// The amount that can be borrowed before either the vault runs out of liquidity, or the
+    /// utilization ceiling is reached
+    pub fn borrowable(&self, max_utilization: Decimal) -> Uint128 {
+        let liquid = self.liquidity();
+        let headroom = self
+            .deposit_pool
+            .size()
+            .mul_floor(max_utilization)
+            .checked_sub(self.debt_pool.size())
+            .unwrap_or_default();
+        min(liquid, headroom)
+    }
+
+    /// Underlying held by the vault that is neither lent out nor reserved for the
+    /// withdrawal queue
+    pub fn liquidity(&self) -> Uint128 {
+        self.deposit_pool
+            .size()
+            .checked_sub(self.debt_pool.size())
+            .and_then(|x| x.checked_sub(self.deposit_pool.ownership(self.queued_shares)))
+            .unwrap_or_default()
+    }
+
     pub fn repay(&mut self, amount: Uint128) -> Result<Uint128, ContractError> {
         if amount.gt(&self.debt_pool.size()) {
             return Err(ContractError::ExcessiveRepay {
@@ -75,6 +210,76 @@ impl State {
         Ok(shares)
     } //*called by execute()
 
+    /// Removes debt shares that will never be repaid, and takes their value from the deposit pool.
+    /// The reserve covers as much of the loss as it can, with the remainder shared pro-rata by
+    /// depositors. Returns the value written off and the part of it absorbed by the reserve
+    pub fn write_off(&mut self, shares: Uint128) -> Result<(Uint128, Uint128), ContractError> {
+        let loss = self.debt_pool.leave(shares)?;
+        let reserve = self.deposit_pool.ownership(self.reserve_shares);
+        let burnt = if loss.ge(&reserve) {
+            self.reserve_shares
+        } else {
+            self.reserve_shares.multiply_ratio(loss, reserve)
+        };
+        let mut absorbed = Uint128::zero();
+        if !burnt.is_zero() {
+            self.reserve_shares -= burnt;
+            absorbed = self.deposit_pool.leave(burnt)?;
+        }
+        self.deposit_pool.withdraw(loss.checked_sub(absorbed)?)?;
+        Ok((loss, absorbed))
+    }
+
+    pub fn fund_reserve(&mut self, amount: Uint128) -> Result<Uint128, ContractError> {
+        let shares = self.deposit_pool.join(amount)?;
+        self.reserve_shares += shares;
+        Ok(shares)
+    }
+
+    /// Releases reserve shares, defaulting to the whole reserve, so that receipt tokens can be
+    /// minted for them
+    pub fn sweep_reserve(&mut self, shares: Option<Uint128>) -> Result<Uint128, ContractError> {
+        let shares = shares.unwrap_or(self.reserve_shares);
+        self.reserve_shares = self.reserve_shares.checked_sub(shares)?;
+        Ok(shares)
+    }
+
+    /// Credits income from outside the interest rate model, such as flash loan fees and borrower
+    /// premiums, to depositors. The protocol's cut joins the deposit pool as fee shares, part of
+    /// which is retained in the reserve. Returns the fee shares to mint
+    pub fn credit(&mut self, fee: Uint128, config: &Config) -> Result<Uint128, ContractError> {
+        let protocol = fee.mul_floor(config.fee);
+        self.deposit_pool.deposit(fee.sub(protocol))?;
+        let shares = match self.deposit_pool.join(protocol) {
+            Ok(shares) => shares,
+            // Too small to issue shares, so depositors keep it
+            Err(SharePoolError::Zero(_)) => {
+                self.deposit_pool.deposit(protocol)?;
+                Uint128::zero()
+            }
+            Err(err) => return Err(err.into()),
+        };
+        let reserve = shares.mul_floor(config.reserve_fraction);
+        self.reserve_shares += reserve;
+        Ok(shares.sub(reserve))
+    }
+
+    /// The underlying borrowed for `shares` of debt, rounded down
+    pub fn borrow_value(&self, shares: Uint128) -> Uint128 {
+        if self.debt_pool.shares().is_zero() {
+            return shares;
+        }
+        self.debt_pool.ownership(shares)
+    }
+
+    /// The underlying needed to repay `shares` of debt, rounded up
+    pub fn repay_value(&self, shares: Uint128) -> Result<Uint128, ContractError> {
+        if self.debt_pool.shares().is_zero() {
+            return Ok(shares);
+        }
+        Ok(shares.checked_mul_ceil((self.debt_pool.size(), self.debt_pool.shares()))?)
+    }
+
     pub fn utilization(&self) -> Decimal {
         // We consider accrued interest and debt in the utilization rate
         if self.deposit_pool.size().is_zero() {
@@ -89,33 +294,69 @@ impl State {
         }
     }
 
-    pub fn debt_rate(&self, interest: &Interest) -> StdResult<Decimal> {
-        interest.rate(self.utilization())
+    pub fn debt_rate(&self, interest: &InterestModel, ramp: Option<&Ramp>) -> StdResult<Decimal> {
+        let rate = interest.rate(self.utilization(), self.rate_at_target)?;
+        match ramp {
+            Some(ramp) => Ok(ramp.blend(
+                ramp.from.rate(self.utilization(), None)?,
+                rate,
+                ramp.progress(self.last_updated),
+            )),
+            None => Ok(rate),
+        }
     }
 
-    pub fn lend_rate(&self, interest: &Interest) -> StdResult<Decimal> {
-        Ok(interest.rate(self.utilization())? * self.utilization())
+    /// The rate earned on deposits, including borrower premiums paid on top of the pool's
+    /// debt rate
+    pub fn lend_rate(&self, interest: &InterestModel, ramp: Option<&Ramp>) -> StdResult<Decimal> {
+        let rate = self.debt_rate(interest, ramp)? * self.utilization();
+        let premium = self
+            .debt_pool
+            .ownership(Uint128::try_from(self.premium_rate.to_uint_floor())?);
+        if premium.is_zero() {
+            return Ok(rate);
+        }
+        Ok(rate + Decimal::from_ratio(premium, self.deposit_pool.size()))
     }
 
     pub fn calculate_interest(
         &mut self,
-        interest: &Interest,
+        config: &Config,
         to: Timestamp,
-        fee_rate: Decimal,
-    ) -> Result<(Uint128, Uint128), ContractError> {
-        let rate = Decimal256::from(self.debt_rate(interest)?);
+    ) -> Result<(Uint128, Uint128, Decimal), ContractError> {
         let seconds = to.seconds().sub(self.last_updated.seconds());
+        // Utilization is constant since the last update, so the adaptive curve moves according
+        // to its deviation from target over the whole period
+        let (mut rate, rate_at_target) =
+            config
+                .interest
+                .accrue(self.rate_at_target, self.utilization(), seconds)?;
+        self.rate_at_target = rate_at_target;
+        if let Some(ramp) = &config.ramp {
+            rate = ramp.blend(
+                ramp.from.rate(self.utilization(), None)?,
+                rate,
+                ramp.average_progress(self.last_updated, to),
+            );
+        }
+        let annual_rate = rate;
+        let rate = Decimal256::from(rate);
         let part = Decimal256::from_ratio(seconds, 31_536_000u128);
 
-        let interest_decimal = Decimal256::from_ratio(self.debt_pool.size(), 1u128)
-            .mul(rate)
-            .mul(part);
+        // Compounding charges e^(rt) - 1 over the period, so that the debt grows at the same
+        // rate however often interest is accrued
+        let debt = Decimal256::from_ratio(self.debt_pool.size(), 1u128);
+        let interest_decimal = if config.compounding {
+            debt.checked_mul(compound(rate.mul(part))?)?
+        } else {
+            debt.mul(rate).mul(part)
+        };
 
         // add pending_interest to interest
         let interest_scaled = DecimalScaled::from(interest_decimal);
 
         // collect the fee for the protocol
-        let fee_rate_scaled = DecimalScaled::from(Decimal256::from(fee_rate));
+        let fee_rate_scaled = DecimalScaled::from(Decimal256::from(config.fee));
         // add the fee to the pending fees
         let fee_accrued = interest_scaled.mul(fee_rate_scaled);
 
@@ -133,17 +374,21 @@ impl State {
         self.pending_fees = fee_frac;
         self.pending_interest = interest_frac;
 
-        Ok((Uint128::try_from(interest)?, Uint128::try_from(fee)?))
+        Ok((
+            Uint128::try_from(interest)?,
+            Uint128::try_from(fee)?,
+            annual_rate,
+        ))
     }
 
     pub fn distribute_interest(
         &mut self,
         env: &Env,
         config: &Config,
-    ) -> Result<Uint128, ContractError> {
+    ) -> Result<Accrual, ContractError> {
+        let seconds = env.block.time.seconds().sub(self.last_updated.seconds());
         // Calculate interest charged on total debt since last update
-        let (interest, mut fee) =
-            self.calculate_interest(&config.interest, env.block.time, config.fee)?;
+        let (interest, mut fee, rate) = self.calculate_interest(config, env.block.time)?;
         let mut shares = Uint128::zero();
 
         // deposit the protocol fee to the deposit pool to issue shares
@@ -167,13 +412,78 @@ impl State {
         self.debt_pool.deposit(interest.add(fee))?;
         self.last_updated = env.block.time;
 
-        Ok(shares)
+        // Retain part of the fee in the reserve, the rest is minted to the fee address
+        let reserve = shares.mul_floor(config.reserve_fraction);
+        self.reserve_shares += reserve;
+
+        // Premiums are charged on the debt after the pool's interest
+        let (premium, premium_shares) = self.charge_premiums(config, seconds)?;
+
+        Ok(Accrual {
+            seconds,
+            rate,
+            interest,
+            fee,
+            shares: shares.sub(reserve).add(premium_shares),
+            premium,
+        })
+    }
+
+    /// Charges borrower premiums accrued since the last update to the debt pool, crediting them
+    /// to depositors. Premiums are accrued in debt shares, and issued once they amount to a whole
+    /// share. Returns the amount charged and the fee shares to mint
+    fn charge_premiums(
+        &mut self,
+        config: &Config,
+        seconds: u64,
+    ) -> Result<(Uint128, Uint128), ContractError> {
+        self.premium_accrued += self.premium_rate * Decimal256::from_ratio(seconds, YEAR);
+        let accrued = Uint128::try_from(self.premium_accrued.to_uint_floor())?;
+        let shares = accrued.saturating_sub(self.premium_shares);
+        if shares.is_zero() {
+            return Ok((Uint128::zero(), Uint128::zero()));
+        }
+        let (issued, amount) = self.issue_premium(shares)?;
+        self.premium_shares += issued;
+        Ok((amount, self.credit(amount, config)?))
+    }
+
+    /// Allocates `shares` of premium debt to a borrower, from the premiums already charged to the
+    /// debt pool where there are enough. The rest is charged now. Returns the shares allocated
+    /// and the fee shares to mint
+    pub fn take_premium(
+        &mut self,
+        shares: Uint128,
+        config: &Config,
+    ) -> Result<(Uint128, Uint128), ContractError> {
+        let taken = min(shares, self.premium_shares);
+        self.premium_shares -= taken;
+        let rest = shares.sub(taken);
+        if rest.is_zero() {
+            return Ok((taken, Uint128::zero()));
+        }
+        let (issued, amount) = self.issue_premium(rest)?;
+        Ok((taken.add(issued), self.credit(amount, config)?))
+    }
+
+    /// Issues up to `shares` of premium debt for their value rounded down, so that rounding is
+    /// never charged to a borrower. Returns the shares issued, which may fall short by one, and
+    /// the amount charged for them
+    fn issue_premium(&mut self, shares: Uint128) -> Result<(Uint128, Uint128), ContractError> {
+        let amount = self.borrow_value(shares);
+        match self.debt_pool.join(amount) {
+            Ok(issued) => Ok((issued, amount)),
+            // Too small to issue shares, so it waits until more has accrued
+            Err(SharePoolError::Zero(_)) => Ok((Uint128::zero(), Uint128::zero())),
+            Err(err) => Err(err.into()),
+        }
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::{interest::Fixed, msg::Pause};
     use cosmwasm_std::{testing::mock_env, Decimal};
     use rujira_rs::{ghost::vault::Interest, DecimalScaled};
 
@@ -186,26 +496,36 @@ mod tests {
 
         let config = Config {
             denom: "test".to_string(),
-            interest: Interest {
+            interest: InterestModel::Kink(Interest {
                 target_utilization: Decimal::from_ratio(8u128, 10u128),
                 base_rate: Decimal::from_ratio(1u128, 1000000u128), // 0.0001% per year
                 step1: Decimal::from_ratio(20u128, 100u128),
                 step2: Decimal::from_ratio(100u128, 100u128),
-            },
+            }),
             fee: Decimal::from_ratio(1u128, 10u128), // 10% fee
             fee_address: cosmwasm_std::Addr::unchecked("fee_addr"),
+            guardian: None,
+            pause: Pause::default(),
+            deposit_cap: None,
+            depositor_cap: None,
+            max_utilization: Decimal::one(),
+            reserve_fraction: Decimal::zero(),
+            ramp: None,
+            compounding: false,
+            flash_fee: Decimal::zero(),
+            flash_limit: None,
         };
 
         // Deposit 1000, borrow 800
         state.deposit(Uint128::new(1000)).unwrap();
-        state.borrow(Uint128::new(800)).unwrap();
+        state.borrow(Uint128::new(800), Decimal::one()).unwrap();
 
         // Wait 1 second
         let mut env = mock_env();
         env.block.time = state.last_updated.plus_seconds(1);
 
         // Distribute interest
-        let shares = state.distribute_interest(&env, &config).unwrap();
+        let shares = state.distribute_interest(&env, &config).unwrap().shares;
 
         // No shares minted (fee_int = 0)
         assert_eq!(shares, Uint128::zero());
@@ -234,26 +554,36 @@ mod tests {
 
         let config = Config {
             denom: "test".to_string(),
-            interest: Interest {
+            interest: InterestModel::Kink(Interest {
                 target_utilization: Decimal::from_ratio(8u128, 10u128),
                 base_rate: Decimal::from_ratio(10u128, 100u128), // 10% base rate
                 step1: Decimal::from_ratio(20u128, 100u128),
                 step2: Decimal::from_ratio(100u128, 100u128),
-            },
+            }),
             fee: Decimal::from_ratio(1u128, 10u128), // 10% fee
             fee_address: cosmwasm_std::Addr::unchecked("fee_addr"),
+            guardian: None,
+            pause: Pause::default(),
+            deposit_cap: None,
+            depositor_cap: None,
+            max_utilization: Decimal::one(),
+            reserve_fraction: Decimal::zero(),
+            ramp: None,
+            compounding: false,
+            flash_fee: Decimal::zero(),
+            flash_limit: None,
         };
 
         // Deposit 1000, borrow 800
         state.deposit(Uint128::new(1000)).unwrap();
-        state.borrow(Uint128::new(800)).unwrap();
+        state.borrow(Uint128::new(800), Decimal::one()).unwrap();
 
         // Wait 1 year
         let mut env = mock_env();
         env.block.time = state.last_updated.plus_seconds(31_536_000);
 
         // Distribute interest
-        let shares = state.distribute_interest(&env, &config).unwrap();
+        let shares = state.distribute_interest(&env, &config).unwrap().shares;
 
         // 24 shares minted for protocol fee
         assert_eq!(shares, Uint128::new(24));
@@ -270,4 +600,90 @@ mod tests {
         assert_eq!(state.deposit_pool.size().u128() - 1000, 240);
         assert_eq!(state.debt_pool.size().u128() - 800, 240);
     }
+
+    #[test]
+    fn test_borrow_liquidity() {
+        let env = mock_env();
+        let mut storage = cosmwasm_std::testing::MockStorage::new();
+        State::init(&mut storage, &env).unwrap();
+        let mut state = State::load(&storage).unwrap();
+
+        state.deposit(Uint128::new(1000)).unwrap();
+
+        // The vault can't lend more than it holds
+        match state.borrow(Uint128::new(1001), Decimal::one()) {
+            Err(ContractError::InsufficientLiquidity {
+                available,
+                requested,
+            }) => {
+                assert_eq!(available, Uint128::new(1000));
+                assert_eq!(requested, Uint128::new(1001));
+            }
+            res => panic!("unexpected {res:?}"),
+        }
+
+        // Nor beyond the utilization ceiling
+        let max_utilization = Decimal::percent(95);
+        state.borrow(Uint128::new(900), max_utilization).unwrap();
+        assert_eq!(state.borrowable(max_utilization), Uint128::new(50));
+        match state.borrow(Uint128::new(51), max_utilization) {
+            Err(ContractError::InsufficientLiquidity { available, .. }) => {
+                assert_eq!(available, Uint128::new(50));
+            }
+            res => panic!("unexpected {res:?}"),
+        }
+        state.borrow(Uint128::new(50), max_utilization).unwrap();
+        assert_eq!(state.utilization(), max_utilization);
+        assert_eq!(state.borrowable(max_utilization), Uint128::zero());
+    }
+
+    fn accrue_year(compounding: bool, steps: u64) -> Uint128 {
+        let env = mock_env();
+        let mut storage = cosmwasm_std::testing::MockStorage::new();
+        State::init(&mut storage, &env).unwrap();
+        let mut state = State::load(&storage).unwrap();
+        let config = Config {
+            denom: "test".to_string(),
+            interest: InterestModel::Fixed(Fixed {
+                rate: Decimal::percent(10),
+            }),
+            fee: Decimal::zero(),
+            fee_address: cosmwasm_std::Addr::unchecked("fee_addr"),
+            guardian: None,
+            pause: Pause::default(),
+            deposit_cap: None,
+            depositor_cap: None,
+            max_utilization: Decimal::one(),
+            reserve_fraction: Decimal::zero(),
+            ramp: None,
+            compounding,
+            flash_fee: Decimal::zero(),
+            flash_limit: None,
+        };
+
+        state.deposit(Uint128::new(2_000_000)).unwrap();
+        state
+            .borrow(Uint128::new(1_000_000), Decimal::one())
+            .unwrap();
+
+        let start = state.last_updated;
+        for i in 1..=steps {
+            let mut env = mock_env();
+            env.block.time = start.plus_seconds(31_536_000 * i / steps);
+            state.distribute_interest(&env, &config).unwrap();
+        }
+        state.debt_pool.size() - Uint128::new(1_000_000)
+    }
+
+    #[test]
+    fn test_compounding() {
+        // Simple interest depends on how often it is accrued
+        assert_eq!(accrue_year(false, 1), Uint128::new(100_000));
+        assert_eq!(accrue_year(false, 365), Uint128::new(105_155));
+
+        // Compounding matches e^0.1 - 1 = 10.5170918% whether accrued once or daily
+        assert_eq!(accrue_year(true, 1), Uint128::new(105_170));
+        assert_eq!(accrue_year(true, 365), Uint128::new(105_170));
+        assert_eq!(accrue_year(true, 8_760), Uint128::new(105_170));
+    }
 }